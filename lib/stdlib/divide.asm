@@ -0,0 +1,41 @@
+;--------------------------------------------------------------------------
+; DIVIDE
+; Performs r0 % r1 and r0 / r1 for r0 >= 0 and r1 > 0, halts if r1 = 0
+; Returns r0 = remainder, r1 = quotient, r6 must point to a stack
+;--------------------------------------------------------------------------
+
+    .GLOBAL DIVIDE
+
+DIVIDE
+    STR	R1, R6, #-1	; save registers
+    STR	R2, R6, #-2
+    STR	R3, R6, #-3
+    ADD	R6, R6, #-3
+
+    NOT	R2, R1
+    ADD	R2, R2, #1
+    BRz	DIVIDE_BY_ZERO	; halt if dividing by zero
+
+    AND	R1, R1, #0	; clear R1 (quotient)
+
+DIVIDE_LOOP
+    ADD	R1, R1, #1
+    ADD	R0, R0, R2	; R0 -= divisor
+    BRp	DIVIDE_LOOP	; R0 - divisor > 0, so keep looping
+    BRz	DIVIDE_END	; R0 = 0, so we finished exactly
+
+			; R0 < 0, so we subtracted an extra one
+    LDR	R2, R6, #2	; add it back in
+    ADD	R1, R1, #-1
+    ADD	R0, R0, R2
+
+DIVIDE_END
+    LDR	R3, R6, #0
+    LDR	R2, R6, #1
+    ADD	R6, R6, #3
+    RET
+
+DIVIDE_BY_ZERO
+    HALT
+
+.END
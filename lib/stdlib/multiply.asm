@@ -0,0 +1,45 @@
+;--------------------------------------------------------------------------
+; MULTIPLY
+; Performs multiplication using bit shifting
+; Returns r0 = r0 * r1, r6 must point to a stack
+;--------------------------------------------------------------------------
+
+    .GLOBAL MULTIPLY
+
+MULTIPLY
+    ADD	R0, R0, #0
+    BRz	MULTIPLY_ZERO	; return 0 if R0 = 0
+    ADD	R1, R1, #0
+    BRz	MULTIPLY_ZERO	; return 0 if R1 = 0
+
+    STR	R1, R6, #-1	; save registers
+    STR	R2, R6, #-2
+    STR	R3, R6, #-3
+    STR	R4, R6, #-4
+    ADD	R6, R6, #-4
+
+    AND	R2, R2, #0	; clear R2 (product)
+    ADD	R3, R2, #1	; set R3 = 1 (bit tester)
+
+MULTIPLY_LOOP			; for each bit in R0
+    AND	R4, R0, R3	; R4 = bit test(R0, R3)
+    BRnz	#1		; only execute next line if bit is set
+    ADD	R2, R2, R1	; product = product + R1
+    ADD	R1, R1, R1	; R1 << 1
+    ADD	R3, R3, R3	; R3 << 1
+    BRp	MULTIPLY_LOOP
+
+    ADD	R0, R2, #0	; move product to R0
+
+    LDR	R4, R6, #0	; restore registers
+    LDR	R3, R6, #1
+    LDR	R2, R6, #2
+    LDR	R1, R6, #3
+    ADD	R6, R6, #4
+    RET
+
+MULTIPLY_ZERO
+    AND	R0, R0, #0
+    RET
+
+.END
@@ -0,0 +1,62 @@
+;--------------------------------------------------------------------------
+; PRINT_NUMBER
+; Prints r0 as a signed decimal number, for r0 > -32768
+; Registers are preserved, r6 must point to a stack
+;--------------------------------------------------------------------------
+
+    .GLOBAL PRINT_NUMBER
+    .EXTERN DIVIDE
+
+PRINT_NUMBER
+    STR	R0, R6, #-1	; save registers
+    STR	R1, R6, #-2
+    STR	R2, R6, #-3
+    STR	R7, R6, #-4
+    ADD	R6, R6, #-4
+
+    ADD	R2, R0, #0	; R2 = number left to print
+    BRzp	PRINT_NUMBER_SPLIT
+    LD	R0, PRINT_NUMBER_MINUS
+    OUT
+    NOT	R2, R2		; R2 = -R2
+    ADD	R2, R2, #1
+
+PRINT_NUMBER_SPLIT
+    AND	R0, R0, #0	; push 0 to mark the last digit
+    ADD	R6, R6, #-1
+    STR	R0, R6, #0
+
+PRINT_NUMBER_DIGIT		; push digits, least significant first
+    ADD	R0, R2, #0
+    AND	R1, R1, #0
+    ADD	R1, R1, #10
+    JSR	DIVIDE		; R0 = digit, R1 = rest
+    ADD	R2, R1, #0
+    LD	R1, PRINT_NUMBER_ZERO
+    ADD	R0, R0, R1
+    ADD	R6, R6, #-1
+    STR	R0, R6, #0
+    ADD	R2, R2, #0
+    BRp	PRINT_NUMBER_DIGIT
+
+PRINT_NUMBER_OUT		; pop and print digits until the mark
+    LDR	R0, R6, #0
+    ADD	R6, R6, #1
+    ADD	R0, R0, #0
+    BRz	PRINT_NUMBER_EXIT
+    OUT
+    BRnzp	PRINT_NUMBER_OUT
+
+PRINT_NUMBER_EXIT
+    LDR	R7, R6, #0	; restore registers
+    LDR	R2, R6, #1
+    LDR	R1, R6, #2
+    LDR	R0, R6, #3
+    ADD	R6, R6, #4
+    RET
+
+; data
+    PRINT_NUMBER_MINUS	.FILL	x2D	; '-'
+    PRINT_NUMBER_ZERO	.FILL	x30	; '0'
+
+.END
@@ -0,0 +1,48 @@
+;--------------------------------------------------------------------------
+; RAND
+; Linear congruential generator, seed = (a * seed + c) % m
+; Returns r0 = next random number, store to SEED to reseed
+; r6 must point to a stack
+;--------------------------------------------------------------------------
+
+    .GLOBAL RAND
+    .GLOBAL SEED
+
+RAND
+    ; push onto stack
+    STR R1, R6, #-1
+    STR R2, R6, #-2
+    STR R3, R6, #-3
+    STR R4, R6, #-4
+    STR R5, R6, #-5
+    STR R7, R6, #-6
+    ADD R6, R6, #-6
+    ; seed = (a * seed + c) % m
+    LD  R1, SEED_A
+    LD  R2, SEED
+    AND R0, R0, x0
+RAND_MULTIPLY     ; a * seed
+    ADD R0, R0, R2
+    ADD R1, R1, #-1
+    BRp RAND_MULTIPLY
+    LD  R1, SEED_C
+    ADD R0, R0, R1 ; + C
+    LD  R1, SEED_M
+    AND R0, R0, R1 ; % m
+    ST  R0, SEED
+    ; pop from stack
+    LDR R7, R6, #0
+    LDR R5, R6, #1
+    LDR R4, R6, #2
+    LDR R3, R6, #3
+    LDR R2, R6, #4
+    LDR R1, R6, #5
+    ADD R6, R6, #6
+    RET
+
+SEED   .FILL xAC34 ; made up number
+SEED_A .FILL #15245 ; made up
+SEED_C .FILL #131 ; smaller incrementer
+SEED_M .FILL x7FFF ; masks to last bit (prevents negative)
+
+.END
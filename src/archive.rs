@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    object::Object,
+};
+
+/// Format version written to `version`, raised on incompatible changes
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// A static library of relocatable objects, written as `.lib`
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "index": { "DIVIDE": 0, "PRINT_NUMBER": 1 },
+///   "members": [{ "version": 1, "name": "lib/stdlib/divide.asm", ... }]
+/// }
+/// ```
+///
+/// `index` maps every exported label to the member defining it, so the
+/// linker finds the members it needs without reading their symbols.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Archive {
+    pub version: u32,
+    pub index: BTreeMap<String, usize>,
+    pub members: Vec<Object>,
+}
+
+impl Archive {
+    /// Bundle objects, failing if two of them export the same label
+    pub fn new(members: Vec<Object>) -> Result<Self> {
+        let mut index = BTreeMap::new();
+        for (i, member) in members.iter().enumerate() {
+            for symbol in member.exports() {
+                if let Some(other) = index.insert(symbol.name.clone(), i) {
+                    return Err(Error::with_message(
+                        ErrorKind::ObjectError,
+                        format!(
+                            "{} is exported by both {} and {}",
+                            symbol.name, members[other].name, member.name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            version: ARCHIVE_VERSION,
+            index,
+            members,
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let archive: Self = serde_json::from_str(text)
+            .map_err(|e| Error::with_message(ErrorKind::ObjectError, e.to_string()))?;
+        if archive.version != ARCHIVE_VERSION {
+            return Err(Error::with_message(
+                ErrorKind::ObjectError,
+                format!("unsupported archive version {}", archive.version),
+            ));
+        }
+        if let Some((name, _)) = archive
+            .index
+            .iter()
+            .find(|(_, &member)| member >= archive.members.len())
+        {
+            return Err(Error::with_message(
+                ErrorKind::ObjectError,
+                format!("index entry {name} names a missing member"),
+            ));
+        }
+        Ok(archive)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("archives serialize") + "\n"
+    }
+
+    /// Index of the member exporting `name`
+    pub fn member_defining(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::{
+        assemble,
+        linker::{LinkScript, Linker},
+        simulator::Simulator,
+        AssemblerOptions,
+    };
+
+    /// Assemble the standard library from `lib/stdlib`, in file name order
+    fn stdlib() -> Archive {
+        let mut paths: Vec<_> = fs::read_dir("lib/stdlib")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+
+        let members = paths
+            .iter()
+            .map(|path| {
+                let options = AssemblerOptions::builder()
+                    .name(path.display().to_string())
+                    .relocatable(true)
+                    .build();
+                let source = fs::read_to_string(path).unwrap();
+                let program = assemble(&source, &options).unwrap();
+                program.object().unwrap().clone()
+            })
+            .collect();
+        Archive::new(members).unwrap()
+    }
+
+    #[test]
+    fn test_stdlib() {
+        let archive = stdlib();
+        assert_eq!(
+            Archive::from_path(Path::new("lib/stdlib.lib")).unwrap(),
+            archive
+        );
+        assert_eq!(
+            archive.index.keys().collect::<Vec<_>>(),
+            ["DIVIDE", "MULTIPLY", "PRINT_NUMBER", "RAND", "SEED"]
+        );
+
+        let source = "\
+.ORIG x3000
+.EXTERN PRINT_NUMBER
+    LD R6, STACK
+    LD R0, NUMBER
+    JSR PRINT_NUMBER
+    HALT
+STACK .FILL xFE00
+NUMBER .FILL #-1024
+.END
+";
+        let options = AssemblerOptions::builder().relocatable(true).build();
+        let program = assemble(source, &options).unwrap();
+        let mut linker = Linker::new(LinkScript::default());
+        linker.add_object(program.object().unwrap().clone());
+        linker.add_archive(archive);
+        let linked = linker.link().unwrap();
+
+        // Only the members needed are pulled in, with what they need in turn
+        let members: Vec<_> = linked
+            .placements
+            .iter()
+            .map(|p| p.object.as_str())
+            .collect();
+        assert_eq!(
+            members,
+            [
+                "<source>",
+                "lib/stdlib/print_number.asm",
+                "lib/stdlib/divide.asm"
+            ]
+        );
+
+        let mut sim = Simulator::new(io::empty(), Vec::new());
+        sim.load(&linked.image).unwrap();
+        sim.run().unwrap();
+        assert_eq!(String::from_utf8_lossy(sim.output()), "-1024");
+    }
+
+    #[test]
+    fn test_archive_errors() {
+        let member = stdlib().members[0].clone();
+        let error = Archive::new(vec![member.clone(), member]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[ObjectError] DIVIDE is exported by both lib/stdlib/divide.asm and lib/stdlib/divide.asm"
+        );
+
+        let error =
+            Archive::from_json(r#"{"version": 1, "index": {"X": 0}, "members": []}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[ObjectError] index entry X names a missing member"
+        );
+    }
+}
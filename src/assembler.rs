@@ -1,125 +1,1092 @@
-use crate::encoder::{
-    encode_add_imm, encode_add_reg, encode_and_imm, encode_and_reg, encode_br, encode_getc,
-    encode_halt, encode_in, encode_jmp, encode_jsr, encode_jsrr, encode_ld, encode_ldi, encode_ldr,
-    encode_lea, encode_not, encode_out, encode_puts, encode_putsp, encode_ret, encode_rti,
-    encode_st, encode_sti, encode_str,
-};
+use crate::debug_info::DebugInfo;
+use crate::encoder::{encode_far_br, encode_far_jsr};
 use crate::enums::OpCode;
-use crate::utils::{resolve_dir, verify_offset};
+use crate::formats::write_image;
+use crate::lint::{Lint, LintLevel, Warning};
+use crate::map::{MapSection, MapSymbol, MemoryMap};
+use crate::options::{AssemblerOptions, Dialect};
+use crate::program::Program;
+use crate::utils::{create_output, decode_lines, split_comment, verify_pc_offset, OutputPaths};
 use crate::{
+    bindings::write_bindings,
     encoder::{encode_blkw, encode_fill, encode_orig, encode_stringz},
     enums::{Directive, MustNext, Token},
     error::{Error, ErrorKind, Result},
-    utils::tokenize,
+    object::{Object, Relocation, RelocationKind, Section, Symbol, OBJECT_VERSION},
+    symbols::{check_symbol_formats, read_sym, write_symbols},
+    target::{Isa, Operands},
+    utils::{tokenize, tokenize_with},
 };
-use byteorder::{BigEndian, WriteBytesExt};
 
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader, BufWriter, Write},
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
 };
 
+/// Deepest chain of `.INCLUDE`s followed before giving up
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A label-targeted BR or JSR seen by the first pass
+struct BranchSite {
+    token: usize,
+    line: usize,
+    addr: u16,
+    op: OpCode,
+    label: String,
+}
+
+/// A branch that was rewritten into a long-range sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct Relaxation {
+    /// Index into [`Assembler::files`]
+    pub file: usize,
+    pub line: usize,
+    pub addr: u16,
+    pub op: OpCode,
+    pub label: String,
+    pub target: u16,
+    pub size: u16,
+}
+
+impl fmt::Display for Relaxation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {:?} {} at x{:04x} -> x{:04x} relaxed to {} words (clobbers R7)",
+            self.line, self.op, self.label, self.addr, self.target, self.size
+        )
+    }
+}
+
+/// What produced the words of a source span
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanKind {
+    Origin,
+    Code,
+    Fill,
+    Blkw,
+    Stringz,
+}
+
+/// Words emitted for a single source line
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceSpan {
+    /// Index into [`Assembler::files`]
+    pub file: usize,
+    /// Line within `file`, 0 for an origin taken from the options
+    pub line: usize,
+    /// Column of the statement after any label, counted from 1
+    pub column: usize,
+    pub addr: u16,
+    pub start: usize,
+    pub len: usize,
+    pub kind: SpanKind,
+}
+
+/// File and line a line of the expanded source came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineOrigin {
+    /// Index into [`Assembler::files`]
+    pub file: usize,
+    pub line: usize,
+}
+
 pub struct Assembler {
     file_path: PathBuf,
+    options: AssemblerOptions,
+    files: Vec<PathBuf>,
+    /// The `.INCLUDE` line that pulled in each file, None for the source itself
+    included_from: Vec<Option<LineOrigin>>,
     lines: Option<Vec<String>>,
+    origins: Vec<LineOrigin>,
     sym_table: HashMap<String, u16>,
+    /// Absolute addresses read from `--symbols` and `.IMPORTSYM` tables
+    imported: HashMap<String, u16>,
     tokens: Vec<Token>,
+    token_lines: Vec<usize>,
+    spans: Vec<SourceSpan>,
     bin: Vec<u16>,
-    outfile: String,
-    debug_mode: bool,
+    output: OutputPaths,
+    branches: Vec<BranchSite>,
+    relaxed: HashSet<usize>,
+    relaxations: Vec<Relaxation>,
+    warnings: Vec<Warning>,
+    /// Labels named by `.GLOBAL` and `.EXTERN`, with the line declaring them
+    globals: Vec<(String, usize)>,
+    externs: Vec<(String, usize)>,
+    relocations: Vec<Relocation>,
+    /// No origin was given, so a relocatable object leaves placement to the linker
+    floating: bool,
+    /// Words laid out by the first pass for instructions and for data directives
+    code_words: usize,
+    data_words: usize,
+    line: usize,
 }
 
 impl Assembler {
-    pub fn new(file_path: PathBuf, outfile: String, debug_mode: bool) -> Self {
+    pub fn new(file_path: PathBuf, output: OutputPaths, options: AssemblerOptions) -> Self {
         Self {
+            files: vec![file_path.clone()],
+            included_from: vec![None],
             file_path,
-            outfile,
+            options,
+            output,
             lines: None,
+            origins: Vec::new(),
             tokens: Vec::new(),
+            token_lines: Vec::new(),
+            spans: Vec::new(),
             bin: Vec::new(),
             sym_table: HashMap::new(),
-            debug_mode,
+            imported: HashMap::new(),
+            branches: Vec::new(),
+            relaxed: HashSet::new(),
+            relaxations: Vec::new(),
+            warnings: Vec::new(),
+            globals: Vec::new(),
+            externs: Vec::new(),
+            relocations: Vec::new(),
+            floating: false,
+            code_words: 0,
+            data_words: 0,
+            line: 0,
         }
     }
 
+    /// Assemble source held in memory, `options.name` is used in diagnostics
+    ///
+    /// Outputs are not written unless `assemble` is called, use `build` to
+    /// only produce the image.
+    pub fn from_source(source: &str, output: OutputPaths, options: AssemblerOptions) -> Self {
+        let mut asm = Self::new(PathBuf::from(&options.name), output, options);
+        asm.set_source(source);
+        asm
+    }
+
+    fn set_source(&mut self, source: &str) {
+        self.lines = Some(source.lines().map(|l| l.to_owned()).collect());
+    }
+
+    /// Assemble without writing any output files
+    pub fn build(&mut self) -> Result<()> {
+        self.read_file()?;
+        self.resolve_layout()?;
+        self.second_pass()?;
+        self.check_lints()?;
+
+        Ok(())
+    }
+
     pub fn assemble(&mut self) -> Result<()> {
         self.debug("Starting assembly process".to_string());
+        self.check_outputs()?;
         self.read_file()?;
-        self.first_pass()?;
-        self.emit_sym_table()?;
+        self.resolve_layout()?;
+        if !self.options.relocatable {
+            self.emit_sym_table()?;
+            self.emit_bindings()?;
+        }
         self.second_pass()?;
-        self.emit_obj_file()?;
+        self.check_lints()?;
+        if self.options.relocatable {
+            self.emit_object()?;
+        } else {
+            self.emit_bin_files()?;
+            if self.options.map {
+                self.emit_map()?;
+            }
+        }
+        if self.options.listing {
+            self.emit_listing()?;
+        }
+        if self.options.debug_info {
+            self.emit_debug_info()?;
+        }
 
         Ok(())
     }
 
     pub fn debug(&self, s: String) {
-        if self.debug_mode {
+        if self.options.debug {
             println!("[Debug] {s}");
         }
     }
 
+    pub fn options(&self) -> &AssemblerOptions {
+        &self.options
+    }
+
+    /// Branches rewritten by the relaxation pass, in source order
+    pub fn relaxations(&self) -> &[Relaxation] {
+        &self.relaxations
+    }
+
+    /// Lints hit by the last assembly, in source order
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Assembled image, origin first
+    pub fn bin(&self) -> &[u16] {
+        &self.bin
+    }
+
+    /// Labels and their addresses
+    pub fn sym_table(&self) -> &HashMap<String, u16> {
+        &self.sym_table
+    }
+
+    /// Words emitted for each source line, in address order
+    pub fn source_map(&self) -> &[SourceSpan] {
+        &self.spans
+    }
+
+    /// The source file followed by every file it included, in inclusion order
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The `.INCLUDE` line each of [`Assembler::files`] was included from
+    pub fn included_from(&self) -> &[Option<LineOrigin>] {
+        &self.included_from
+    }
+
+    /// File and line the assembler stopped at, used to locate errors
+    pub fn error_location(&self) -> Option<(String, usize)> {
+        (self.line > 0).then(|| {
+            let origin = self.origin(self.line);
+            (self.file_name(origin.file), origin.line)
+        })
+    }
+
+    /// Name of the source in diagnostics
+    pub fn source_name(&self) -> String {
+        self.file_name(0)
+    }
+
+    fn file_name(&self, file: usize) -> String {
+        if self.files[file] == Path::new("-") {
+            String::from("<stdin>")
+        } else {
+            self.files[file].display().to_string()
+        }
+    }
+
+    /// Where a line of the expanded source came from
+    fn origin(&self, line: usize) -> LineOrigin {
+        self.origins
+            .get(line.wrapping_sub(1))
+            .copied()
+            .unwrap_or(LineOrigin { file: 0, line })
+    }
+
+    /// Read the source file, `-` reads stdin, and expand its includes
+    ///
+    /// Source given to `from_source` is not read again.
     fn read_file(&mut self) -> Result<()> {
-        let file = BufReader::new(File::open(&self.file_path)?);
-        let lines: Vec<_> = file.lines().map(|l| l.unwrap()).collect();
+        if !self.origins.is_empty() {
+            return Ok(());
+        }
+
+        let source = match self.lines.take() {
+            Some(lines) => lines,
+            None => {
+                let mut bytes = Vec::new();
+                if self.file_path == Path::new("-") {
+                    io::stdin().read_to_end(&mut bytes)?;
+                } else {
+                    File::open(&self.file_path)?.read_to_end(&mut bytes)?;
+                }
+                decode_lines(&bytes, &self.source_name())?
+            }
+        };
+
+        self.imported.clear();
+        for path in self.options.symbol_files.clone() {
+            self.import_symbols(&path)?;
+        }
+
+        self.files.truncate(1);
+        self.included_from.truncate(1);
+        let mut lines = Vec::new();
+        let mut origins = Vec::new();
+        self.expand_includes(source, 0, 0, &mut lines, &mut origins)?;
         self.lines = Some(lines);
+        self.origins = origins;
 
         Ok(())
     }
 
-    fn emit_sym_table(&self) -> Result<()> {
-        let mut sym_path = resolve_dir();
-        sym_path.push(format!("{}.sym", self.outfile));
+    /// Splice included files into `lines`, recording where each line came from
+    fn expand_includes(
+        &mut self,
+        source: Vec<String>,
+        file: usize,
+        depth: usize,
+        lines: &mut Vec<String>,
+        origins: &mut Vec<LineOrigin>,
+    ) -> Result<()> {
+        for (i, line) in source.into_iter().enumerate() {
+            let location = format!("{}:{}", self.file_name(file), i + 1);
+            if let Ok(Some(tokens)) = tokenize(&line) {
+                if let [Token::Dir(Directive::Include), Token::Str(name)] = &tokens[..] {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(Error::with_message(
+                            ErrorKind::IOError,
+                            format!("includes nested too deeply at {location}"),
+                        ));
+                    }
 
-        let mut file = BufWriter::new(File::create(sym_path)?);
+                    let path = self.find_include(name, file).ok_or_else(|| {
+                        Error::with_message(
+                            ErrorKind::IOError,
+                            format!("cannot find \"{name}\" included at {location}"),
+                        )
+                    })?;
+                    let included = decode_lines(&fs::read(&path)?, &path.display().to_string())?;
+                    self.files.push(path);
+                    self.included_from
+                        .push(Some(LineOrigin { file, line: i + 1 }));
+                    let index = self.files.len() - 1;
+                    self.expand_includes(included, index, depth + 1, lines, origins)?;
+                    continue;
+                }
 
-        let mut labels: Vec<_> = self.sym_table.keys().map(|l| l.to_owned()).collect();
-        labels.sort_by(|a, b| {
-            self.sym_table
-                .get(a)
-                .unwrap()
-                .cmp(self.sym_table.get(b).unwrap())
+                if let [Token::Dir(Directive::ImportSym), Token::Str(name)] = &tokens[..] {
+                    let path = self.find_include(name, file).ok_or_else(|| {
+                        Error::with_message(
+                            ErrorKind::IOError,
+                            format!("cannot find symbol table \"{name}\" imported at {location}"),
+                        )
+                    })?;
+                    self.import_symbols(&path)?;
+                    continue;
+                }
+            }
+
+            lines.push(line);
+            origins.push(LineOrigin { file, line: i + 1 });
+        }
+
+        Ok(())
+    }
+
+    /// Look for an included file next to the including file, then in the include paths
+    fn find_include(&self, name: &str, from: usize) -> Option<PathBuf> {
+        let dir = self.files[from].parent().map(Path::to_path_buf);
+        dir.into_iter()
+            .chain(self.options.include_paths.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Add the labels of a symbol table as absolute addresses
+    fn import_symbols(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(path).map_err(|e| {
+            Error::with_message(
+                ErrorKind::IOError,
+                format!("cannot read symbol table {}: {e}", path.display()),
+            )
+        })?;
+        for (label, addr) in read_sym(file)? {
+            self.imported.insert(self.label_key(&label), addr);
+        }
+
+        Ok(())
+    }
+
+    /// Label as stored in the symbol table
+    fn label_key(&self, label: &str) -> String {
+        if self.options.case_sensitive {
+            label.to_owned()
+        } else {
+            label.to_uppercase()
+        }
+    }
+
+    /// Address of a label, falling back to imported symbols and the defines from the options
+    fn lookup(&self, label: &str) -> Result<u16> {
+        if let Some(addr) = self
+            .sym_table
+            .get(label)
+            .or_else(|| self.imported.get(label))
+        {
+            return Ok(*addr);
+        }
+
+        self.options
+            .defines
+            .iter()
+            .find(|(name, _)| self.label_key(name) == label)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                Error::with_message(
+                    ErrorKind::MissingLabelError,
+                    format!("missing label {label}"),
+                )
+            })
+    }
+
+    /// Address of `label` as the operand of the word at `site`
+    ///
+    /// In relocatable mode this records the relocations the linker applies:
+    /// every use of an external label, and absolute uses of local ones.
+    /// External labels resolve so that the field they fill encodes as zero.
+    fn resolve(
+        &self,
+        label: &str,
+        site: u16,
+        kind: RelocationKind,
+        relocations: &mut Vec<Relocation>,
+    ) -> Result<u16> {
+        let external = self.externs.iter().any(|(name, _)| name == label);
+        if !external && !self.sym_table.contains_key(label) {
+            if let Some(&addr) = self.imported.get(label) {
+                self.check_imported_reach(label, addr, site, kind)?;
+                return Ok(addr);
+            }
+        }
+        if !self.options.relocatable {
+            if external && !self.sym_table.contains_key(label) {
+                return Err(Error::with_message(
+                    ErrorKind::MissingLabelError,
+                    format!("{label} is external, assemble with --relocatable and link"),
+                ));
+            }
+            return self.lookup(label);
+        }
+
+        let target = if external {
+            match kind {
+                RelocationKind::Abs16 => 0,
+                _ => site.wrapping_add(1),
+            }
+        } else {
+            let target = self.lookup(label)?;
+            if kind != RelocationKind::Abs16 || !self.sym_table.contains_key(label) {
+                return Ok(target);
+            }
+            target
+        };
+        relocations.push(Relocation {
+            offset: site.wrapping_sub(self.bin[0]),
+            kind,
+            symbol: label.to_owned(),
         });
+        Ok(target)
+    }
 
-        for label in labels {
-            file.write_all(
+    /// Fail unless the PC-relative field of the word at `site` reaches an imported address
+    fn check_imported_reach(
+        &self,
+        label: &str,
+        addr: u16,
+        site: u16,
+        kind: RelocationKind,
+    ) -> Result<()> {
+        let Some(bits) = kind.offset_bits() else {
+            return Ok(());
+        };
+        if self.floating {
+            return Err(Error::with_message(
+                ErrorKind::ObjectError,
+                format!("imported label {label} is absolute, a section without .ORIG can only use it in .FILL"),
+            ));
+        }
+        if verify_pc_offset(addr.wrapping_sub(site.wrapping_add(1)), bits).is_err() {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
                 format!(
-                    "//\t{}\t\t{:x}\n",
-                    label,
-                    self.sym_table.get(&label).unwrap()
-                )
-                .as_bytes(),
-            )?;
+                    "imported label {label} at x{addr:04X} is out of range of the {bits}-bit PC offset at x{site:04X}, load its address with .FILL"
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check the `.GLOBAL` and `.EXTERN` declarations against the labels
+    fn check_linkage(&mut self) -> Result<()> {
+        for (label, line) in &self.globals {
+            if !self.sym_table.contains_key(label) {
+                self.line = *line;
+                return Err(Error::with_message(
+                    ErrorKind::ObjectError,
+                    format!("exported label {label} is not defined"),
+                ));
+            }
+        }
+        for (label, line) in &self.externs {
+            if self.sym_table.contains_key(label) {
+                self.line = *line;
+                return Err(Error::with_message(
+                    ErrorKind::ObjectError,
+                    format!("{label} is both defined and external"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The relocatable object, None unless assembling with `relocatable`
+    pub fn object(&self) -> Option<Object> {
+        if !self.options.relocatable {
+            return None;
         }
+        let (&origin, words) = self.bin.split_first()?;
+
+        let mut symbols: Vec<_> = self
+            .sym_table
+            .iter()
+            .map(|(name, addr)| Symbol {
+                name: name.clone(),
+                section: 0,
+                offset: addr.wrapping_sub(origin),
+                global: self.globals.iter().any(|(global, _)| global == name),
+            })
+            .collect();
+        symbols.sort_by(|a, b| a.offset.cmp(&b.offset).then_with(|| a.name.cmp(&b.name)));
+
+        let mut imports: Vec<_> = self.externs.iter().map(|(name, _)| name.clone()).collect();
+        imports.sort();
+
+        Some(Object {
+            version: OBJECT_VERSION,
+            name: self.source_name(),
+            sections: vec![Section {
+                name: String::from("text"),
+                origin: (!self.floating).then_some(origin),
+                words: words.to_vec(),
+                data_words: self.data_words,
+                relocations: self.relocations.clone(),
+            }],
+            symbols,
+            imports,
+        })
+    }
+
+    /// Placement, symbol references and word counts of the image
+    pub fn memory_map(&self) -> MemoryMap {
+        let mut sections = Vec::new();
+        if let Some((&origin, words)) = self.bin.split_first() {
+            sections.push(MapSection {
+                name: self.source_name(),
+                origin,
+                len: words.len() as u16 * self.options.isa.target().word_size(),
+            });
+        }
+
+        let mut defined_in = HashMap::new();
+        let mut referenced_by: HashMap<&str, Vec<String>> = HashMap::new();
+        for (i, token) in self.tokens.iter().enumerate() {
+            let Token::Label(label) = token else {
+                continue;
+            };
+            let file = self.file_name(self.origin(self.token_lines[i]).file);
+            if self.is_definition(i) {
+                defined_in.insert(label.as_str(), file);
+            } else if !matches!(
+                self.tokens[i - 1],
+                Token::Dir(Directive::Global | Directive::Extern)
+            ) {
+                referenced_by.entry(label).or_default().push(file);
+            }
+        }
+
+        let symbols = self
+            .sym_table
+            .iter()
+            .map(|(name, addr)| MapSymbol {
+                name: name.clone(),
+                addr: *addr,
+                defined_in: defined_in
+                    .get(name.as_str())
+                    .cloned()
+                    .unwrap_or_else(|| self.source_name()),
+                referenced_by: referenced_by.remove(name.as_str()).unwrap_or_default(),
+            })
+            .collect();
+
+        MemoryMap::new(sections, symbols, self.code_words, self.data_words)
+    }
+
+    fn emit_map(&self) -> Result<()> {
+        let Some(path) = self.output.resolve("map") else {
+            return Ok(());
+        };
+
+        let mut file = create_output(&path)?;
+        file.write_all(self.memory_map().to_string().as_bytes())?;
         file.flush()?;
 
+        Ok(())
+    }
+
+    fn emit_object(&self) -> Result<()> {
+        let (Some(path), Some(object)) = (self.output.resolve("rel"), self.object()) else {
+            return Ok(());
+        };
+
+        let mut file = create_output(&path)?;
+        file.write_all(object.to_json().as_bytes())?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Lex a line, with the mnemonics of the registered extensions
+    fn tokenize(&self, line: &str) -> Result<Option<Vec<Token>>> {
+        let extensions = &self.options.extensions;
+        tokenize_with(line, &|word| extensions.find(word).is_some())
+    }
+
+    /// Apply the dialect and case rules to a freshly lexed token
+    fn normalize(&self, token: &mut Token) {
+        match token {
+            Token::Label(label) if !self.options.case_sensitive => *label = label.to_uppercase(),
+            Token::Op(OpCode::Br) if self.options.dialect == Dialect::Lc3as => {
+                *token = Token::Op(OpCode::Brnzp)
+            }
+            _ => {}
+        }
+    }
+
+    /// Read mnemonics the target lacks as labels, failing where one is used as an instruction
+    fn check_mnemonics(&self, line: &str, mut tokens: Vec<Token>) -> Result<Vec<Token>> {
+        let isa = self.options.isa.target();
+        let unsupported = |op: OpCode| {
+            Error::with_message(
+                ErrorKind::ParseOpCodeError,
+                format!(
+                    "{} is not an instruction of the {} target",
+                    format!("{op:?}").to_uppercase(),
+                    isa.name()
+                ),
+            )
+        };
+
+        if let Token::Op(op) = tokens[0] {
+            if !isa.supports(op) {
+                // The rest of a label definition was split as operands, tokenize it again
+                let (label, rest) = line
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((line.trim(), ""));
+                let mut retokenized = vec![Token::Label(label.to_owned())];
+                retokenized.extend(self.tokenize(rest)?.unwrap_or_default());
+                if matches!(
+                    retokenized.get(1),
+                    None | Some(Token::Op(_) | Token::Dir(_))
+                ) {
+                    tokens = retokenized;
+                } else {
+                    return Err(unsupported(op));
+                }
+            }
+        }
+        // Operands are single words, so the source spelling of token `i` is word `i`
+        let words: Vec<_> = split_comment(line)
+            .0
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .collect();
+        for i in 1..tokens.len() {
+            let Token::Op(op) = tokens[i] else {
+                continue;
+            };
+            if isa.supports(op) {
+                continue;
+            }
+            if i == 1 && matches!(tokens[0], Token::Label(_)) {
+                return Err(unsupported(op));
+            }
+            let word = words.get(i).ok_or_else(|| unsupported(op))?;
+            tokens[i] = Token::Label((*word).to_owned());
+        }
+
+        Ok(tokens)
+    }
+
+    /// Fail before writing anything if a requested output has nowhere to go
+    fn check_outputs(&self) -> Result<()> {
+        let mut images = Vec::new();
+        let mut others = Vec::new();
+        if self.options.relocatable {
+            others.push("rel");
+        } else {
+            images.extend(self.options.output_formats.iter().map(|f| f.extension()));
+            others.extend(self.options.bindings.iter().map(|b| b.extension()));
+            if self.options.map {
+                others.push("map");
+            }
+        }
+        if self.options.listing {
+            others.push("lst");
+        }
+        if self.options.debug_info {
+            others.push("dbg.json");
+        }
+        self.output.check_written(&images, &others)
+    }
+
+    fn emit_sym_table(&self) -> Result<()> {
+        for format in &self.options.symbol_formats {
+            let Some(sym_path) = self.output.resolve(format.extension()) else {
+                continue;
+            };
+
+            let mut file = create_output(&sym_path)?;
+            write_symbols(&mut file, *format, &self.sym_table)?;
+            file.flush()?;
+        }
+
         self.debug("Symbol Table".to_owned());
         self.debug(format!("{:#x?}", self.sym_table));
 
         Ok(())
     }
 
-    fn emit_obj_file(&self) -> Result<()> {
-        let mut bin_path = resolve_dir();
-        bin_path.push(format!("{}.obj", self.outfile));
+    fn emit_bindings(&self) -> Result<()> {
+        let source_name = self.source_name();
+        let name = Path::new(&source_name)
+            .file_name()
+            .map_or(source_name.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            });
+        for format in &self.options.bindings {
+            let Some(path) = self.output.resolve(format.extension()) else {
+                continue;
+            };
+
+            let mut file = create_output(&path)?;
+            write_bindings(&mut file, *format, &name, &self.sym_table)?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn emit_bin_files(&self) -> Result<()> {
+        for (i, format) in self.options.output_formats.iter().enumerate() {
+            let Some(bin_path) = self.output.resolve_image(format.extension(), i == 0) else {
+                continue;
+            };
+
+            let mut file = create_output(&bin_path)?;
+            write_image(
+                &mut file,
+                *format,
+                self.options.endian,
+                self.options.isa.target().word_size(),
+                &self.bin,
+            )?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the first pass, repeating it while relaxation rewrites branches
+    ///
+    /// Relaxed branches only ever grow, so the layout is stable once a pass
+    /// relaxes nothing new.
+    fn resolve_layout(&mut self) -> Result<()> {
+        if self.options.isa != Isa::Lc3 && (self.options.relax || self.options.relocatable) {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                String::from("relaxation and relocatable objects need the lc3 target"),
+            ));
+        }
+        check_symbol_formats(&self.options.symbol_formats)?;
+        self.options.extensions.check()?;
+
+        self.first_pass()?;
+        while self.options.relax && self.relax_branches() {
+            self.first_pass()?;
+        }
+
+        let mut relaxations = Vec::new();
+        for site in self.branches.iter() {
+            if self.relaxed.contains(&site.token) {
+                let origin = self.origin(site.line);
+                relaxations.push(Relaxation {
+                    file: origin.file,
+                    line: origin.line,
+                    addr: site.addr,
+                    op: site.op,
+                    label: site.label.clone(),
+                    target: self.lookup(&site.label)?,
+                    size: relaxed_size(site.op),
+                });
+            }
+        }
+        self.relaxations = relaxations;
+        for relaxation in &self.relaxations {
+            self.debug(format!("Relaxed {relaxation}"));
+        }
+
+        if self.options.relocatable {
+            self.check_linkage()?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark every out-of-range branch as relaxed, return true if any were added
+    fn relax_branches(&mut self) -> bool {
+        let mut changed = false;
+        for site in &self.branches {
+            if self.relaxed.contains(&site.token) {
+                continue;
+            }
+
+            // Missing labels are reported by the second pass
+            if let Ok(addr) = self.lookup(&site.label) {
+                let bit_count = if site.op == OpCode::Jsr { 11 } else { 9 };
+                let offset = addr.wrapping_sub(site.addr.wrapping_add(1));
+                if verify_pc_offset(offset, bit_count).is_err() {
+                    self.relaxed.insert(site.token);
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Collect the warnings enabled in the options, fail on the first denied one
+    fn check_lints(&mut self) -> Result<()> {
+        let mut warnings = Vec::new();
+
+        if self.options.lint_level(Lint::UnusedLabel) != LintLevel::Allow {
+            let references: HashSet<&str> = self
+                .tokens
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !self.is_definition(*i))
+                .filter_map(|(_, token)| match token {
+                    Token::Label(label) => Some(label.as_str()),
+                    _ => None,
+                })
+                .collect();
+
+            for (i, token) in self.tokens.iter().enumerate() {
+                if let Token::Label(label) = token {
+                    if self.is_definition(i) && !references.contains(label.as_str()) {
+                        let origin = self.origin(self.token_lines[i]);
+                        warnings.push(Warning {
+                            lint: Lint::UnusedLabel,
+                            file: self.file_name(origin.file),
+                            line: origin.line,
+                            message: format!("label {label} is never used"),
+                        });
+                    }
+                }
+            }
+        }
+
+        if self.options.lint_level(Lint::RelaxedBranch) != LintLevel::Allow {
+            for relaxation in &self.relaxations {
+                warnings.push(Warning {
+                    lint: Lint::RelaxedBranch,
+                    file: self.file_name(relaxation.file),
+                    line: relaxation.line,
+                    message: format!(
+                        "{:?} {} relaxed to {} words, clobbers R7",
+                        relaxation.op, relaxation.label, relaxation.size
+                    ),
+                });
+            }
+        }
 
-        let mut file = BufWriter::new(File::create(bin_path)?);
-        for &word in &self.bin {
-            file.write_u16::<BigEndian>(word)?;
+        warnings.sort_by_key(|w| (w.file != self.source_name(), w.file.clone(), w.line));
+        if let Some(denied) = warnings
+            .iter()
+            .find(|w| self.options.lint_level(w.lint) == LintLevel::Deny)
+        {
+            return Err(Error::with_message(
+                ErrorKind::LintError,
+                denied.to_string(),
+            ));
         }
+        self.warnings = warnings;
+
+        Ok(())
+    }
+
+    /// A label token is a definition when it starts its line
+    fn is_definition(&self, token: usize) -> bool {
+        token == 0 || self.token_lines[token - 1] != self.token_lines[token]
+    }
+
+    /// Line of the expanded source as shown in the listing, prefixed by its
+    /// file when included
+    fn listing_location(&self, line: usize) -> String {
+        let origin = self.origin(line);
+        match origin.file {
+            0 => origin.line.to_string(),
+            file => format!("{}:{}", self.file_name(file), origin.line),
+        }
+    }
+
+    fn emit_debug_info(&self) -> Result<()> {
+        let Some(path) = self.output.resolve("dbg.json") else {
+            return Ok(());
+        };
+
+        let mut file = create_output(&path)?;
+        let info = DebugInfo::from_program(&Program::from_assembler(self));
+        file.write_all(info.to_json().as_bytes())?;
         file.flush()?;
 
         Ok(())
     }
 
+    /// Column of the statement starting at `token`, after a label on the same line
+    fn statement_column(&self, token: usize) -> usize {
+        let line = self.token_lines[token];
+        let lines = self.lines.as_deref().unwrap_or_default();
+        let Some(text) = lines.get(line.wrapping_sub(1)) else {
+            return 1;
+        };
+
+        let indent = |s: &str| s.len() - s.trim_start().len();
+        let mut column = indent(text);
+        if token > 0
+            && self.token_lines[token - 1] == line
+            && matches!(self.tokens[token - 1], Token::Label(_))
+        {
+            let rest = &text[column..];
+            let label_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            column += label_len + indent(&rest[label_len..]);
+        }
+        column + 1
+    }
+
+    fn emit_listing(&self) -> Result<()> {
+        let Some(lst_path) = self.output.resolve("lst") else {
+            return Ok(());
+        };
+
+        let mut file = create_output(&lst_path)?;
+        self.write_listing(&mut file)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Write the listing: address, hex and binary of each word next to its source line,
+    /// followed by a symbol cross-reference
+    fn write_listing<W: Write>(&self, w: &mut W) -> Result<()> {
+        let lines = self.lines.as_deref().unwrap_or_default();
+        let word_size = self.options.isa.target().word_size();
+        // Origins taken from the options have no line to sit next to
+        let mut spans = self.spans.iter().filter(|span| span.line > 0).peekable();
+
+        for (i, source) in lines.iter().enumerate() {
+            let origin = self.origin(i + 1);
+            let line = origin.line;
+            let mut listed = false;
+
+            while let Some(span) =
+                spans.next_if(|span| span.file == origin.file && span.line == line)
+            {
+                let words = &self.bin[span.start..span.start + span.len];
+                let collapse = matches!(span.kind, SpanKind::Blkw | SpanKind::Stringz);
+
+                for (j, word) in words.iter().enumerate() {
+                    let addr = match span.kind {
+                        SpanKind::Origin => String::from("    "),
+                        _ => format!("{:04X}", span.addr.wrapping_add(j as u16 * word_size)),
+                    };
+                    let text = if j == 0 { source.as_str() } else { "" };
+                    let entry = format!("({addr}) {word:04X}  {word:016b} ({line:4}) {text}");
+                    writeln!(w, "{}", entry.trim_end())?;
+
+                    if collapse && words.len() > 1 {
+                        let last = span.addr.wrapping_add((words.len() as u16 - 1) * word_size);
+                        writeln!(
+                            w,
+                            "{:30}({line:4}) ... {} more words through x{last:04X}",
+                            "",
+                            words.len() - 1
+                        )?;
+                        break;
+                    }
+                }
+                listed = true;
+            }
+
+            if !listed {
+                let entry = format!("{:30}({line:4}) {source}", "");
+                writeln!(w, "{}", entry.trim_end())?;
+            }
+
+            for relaxation in self
+                .relaxations
+                .iter()
+                .filter(|r| r.file == origin.file && r.line == line)
+            {
+                writeln!(w, "{:30}({line:4}) ; {relaxation}", "")?;
+            }
+        }
+
+        // Symbol cross-reference, operand labels count as references
+        let mut definitions: HashMap<&str, String> = HashMap::new();
+        let mut references: HashMap<&str, Vec<String>> = HashMap::new();
+        for (i, token) in self.tokens.iter().enumerate() {
+            if let Token::Label(label) = token {
+                let location = self.listing_location(self.token_lines[i]);
+                if self.is_definition(i) {
+                    definitions.insert(label, location);
+                } else {
+                    references.entry(label).or_default().push(location);
+                }
+            }
+        }
+
+        let mut labels: Vec<_> = self.sym_table.iter().collect();
+        labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+
+        writeln!(w)?;
+        writeln!(w, "Symbol cross-reference")?;
+        writeln!(w, "{:<24} {:<6} {:<8} References", "Symbol", "Addr", "Line")?;
+        for (label, addr) in labels {
+            let line = definitions.get(label.as_str()).cloned().unwrap_or_default();
+            let refs = references
+                .get(label.as_str())
+                .map(|r| r.join(", "))
+                .unwrap_or_default();
+            writeln!(w, "{label:<24} x{addr:04X}  {line:<8} {refs}")?;
+        }
+
+        Ok(())
+    }
+
     fn first_pass(&mut self) -> Result<()> {
-        let mut lc: u16 = 0;
+        let mut lc: u16 = self.options.default_origin.unwrap_or_default();
+        let word_size = self.options.isa.target().word_size();
+        self.tokens.clear();
+        self.token_lines.clear();
+        self.sym_table.clear();
+        self.branches.clear();
+        self.globals.clear();
+        self.externs.clear();
+        self.code_words = 0;
+        self.data_words = 0;
 
         if let Some(lines) = &self.lines {
-            for line in lines {
-                if let Some(mut tokens) = tokenize(line)? {
+            for (line_no, line) in lines.iter().enumerate() {
+                self.line = line_no + 1;
+                if let Some(tokens) = self.tokenize(line)? {
+                    let mut tokens = self.check_mnemonics(line, tokens)?;
+                    for token in tokens.iter_mut() {
+                        self.normalize(token);
+                    }
                     self.debug(format!("[{:x}] {:x?}", lc, tokens));
 
                     let idx = match &tokens[0] {
@@ -132,6 +1099,25 @@ impl Assembler {
 
                     if idx < tokens.len() {
                         match &tokens[idx] {
+                            Token::Op(op) if is_relaxable(*op) => {
+                                let token = self.tokens.len() + idx;
+                                if let Some(Token::Label(label)) = tokens.get(idx + 1) {
+                                    self.branches.push(BranchSite {
+                                        token,
+                                        line: line_no + 1,
+                                        addr: lc,
+                                        op: *op,
+                                        label: label.clone(),
+                                    });
+                                }
+                                let size = if self.relaxed.contains(&token) {
+                                    relaxed_size(*op)
+                                } else {
+                                    1
+                                };
+                                lc = advance(lc, size as usize, word_size)?;
+                                self.code_words += size as usize;
+                            }
                             Token::Dir(Directive::Orig) => {
                                 if let Token::Const(c) = tokens[idx + 1] {
                                     lc = c;
@@ -139,48 +1125,131 @@ impl Assembler {
                             }
                             Token::Dir(Directive::Blkw) => {
                                 if let Token::Const(c) = tokens[idx + 1] {
-                                    lc += c;
+                                    lc = advance(lc, c as usize, word_size)?;
+                                    self.data_words += c as usize;
                                 }
                             }
                             Token::Dir(Directive::Stringz) => {
                                 if let Token::Str(s) = &tokens[idx + 1] {
-                                    lc += s.len() as u16 + 1; // +1 for the extra null-byte at the end
+                                    // +1 for the extra null-byte at the end
+                                    lc = advance(lc, s.len() + 1, word_size)?;
+                                    self.data_words += s.len() + 1;
+                                }
+                            }
+
+                            // Well-formed includes and imports were expanded by read_file
+                            Token::Dir(Directive::Include | Directive::ImportSym) => {}
+
+                            Token::Dir(dir @ (Directive::Global | Directive::Extern)) => {
+                                if let Some(Token::Label(label)) = tokens.get(idx + 1) {
+                                    let names = match dir {
+                                        Directive::Global => &mut self.globals,
+                                        _ => &mut self.externs,
+                                    };
+                                    if !names.iter().any(|(name, _)| name == label) {
+                                        names.push((label.clone(), line_no + 1));
+                                    }
                                 }
                             }
 
                             Token::Dir(Directive::End) => break,
 
+                            Token::Dir(Directive::Fill) => {
+                                lc = advance(lc, 1, word_size)?;
+                                self.data_words += 1;
+                            }
+
                             _ => {
-                                lc += 1;
+                                lc = advance(lc, 1, word_size)?;
+                                self.code_words += 1;
                             }
                         }
                     }
 
+                    self.token_lines
+                        .extend(std::iter::repeat_n(line_no + 1, tokens.len()));
                     self.tokens.append(&mut tokens);
                 }
             }
         }
 
+        self.line = 0;
         Ok(())
     }
 
     fn second_pass(&mut self) -> Result<()> {
-        let mut token_iter = self.tokens.iter();
-        let mut lc;
+        self.bin.clear();
+        self.spans.clear();
+
+        // Declarations may come before the origin, the first pass collected them
+        let mut start = 0;
+        while matches!(
+            self.tokens[start..],
+            [
+                Token::Dir(Directive::Global | Directive::Extern),
+                Token::Label(_),
+                ..
+            ]
+        ) {
+            start += 2;
+        }
 
-        // Tokens should begin with Dir(Orig) and Const(c)
-        // Otherwise syntax error
-        let mut bin = match token_iter.next() {
-            Some(Token::Dir(Directive::Orig)) => {
-                let origin = token_iter.must_next()?.take_const()?;
-                lc = origin;
-                encode_orig(origin)
+        let mut token_iter = self.tokens[start..].iter();
+        let mut lc;
+        self.line = self.token_lines.get(start).copied().unwrap_or_default();
+        let mut relocations = Vec::new();
+        self.floating = false;
+        let isa = self.options.isa.target();
+        let word_size = isa.word_size();
+
+        // Tokens should begin with Dir(Orig) and Const(c), unless the options
+        // give a default origin. Otherwise syntax error
+        let (origin, line) = match (self.tokens.get(start), self.options.default_origin) {
+            (Some(Token::Dir(Directive::Orig)), _) => {
+                token_iter.next();
+                (
+                    token_iter.must_next()?.take_const()?,
+                    self.token_lines[start],
+                )
+            }
+            (_, Some(origin)) => (origin, 0),
+            (_, None) if self.options.relocatable => {
+                self.floating = true;
+                (0, 0)
             }
             _ => return Err(Error::new(ErrorKind::SyntaxError)),
         };
+        if origin % word_size != 0 {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                format!("origin x{origin:04X} is not word aligned"),
+            ));
+        }
+        lc = origin;
+        let mut bin = encode_orig(origin);
+        let location = match line {
+            0 => LineOrigin { file: 0, line: 0 },
+            line => self.origin(line),
+        };
+        self.spans.push(SourceSpan {
+            file: location.file,
+            line: location.line,
+            column: if line == 0 {
+                0
+            } else {
+                self.statement_column(start)
+            },
+            addr: lc,
+            start: self.bin.len(),
+            len: bin.len(),
+            kind: SpanKind::Origin,
+        });
         self.bin.append(&mut bin);
 
         while let Some(token) = token_iter.next() {
+            let token_idx = self.tokens.len() - token_iter.len() - 1;
+            self.line = self.token_lines[token_idx];
+            let addr = lc;
             let mut bin = match token {
                 /* Directive Encoders */
                 Token::Dir(Directive::Fill) => {
@@ -188,255 +1257,85 @@ impl Assembler {
                     let v = match arg {
                         Token::Const(_) => arg.take_const()?,
                         Token::Label(l) => {
-                            let x = self
-                                .sym_table
-                                .get(l)
-                                .ok_or(Error::new(ErrorKind::MissingLabelError));
-                            x?.to_owned()
+                            self.resolve(l, addr, RelocationKind::Abs16, &mut relocations)?
                         }
                         _ => return Err(Error::new(ErrorKind::SyntaxError)),
                     };
-                    lc += 1;
+                    lc = advance(lc, 1, word_size)?;
                     encode_fill(v)
                 }
 
                 Token::Dir(Directive::Blkw) => {
                     let c = token_iter.must_next()?.take_const()?;
-                    lc += c;
+                    lc = advance(lc, c as usize, word_size)?;
                     encode_blkw(c)
                 }
 
                 Token::Dir(Directive::Stringz) => {
                     let s = token_iter.must_next()?.take_str()?;
-                    lc += s.len() as u16 + 1;
+                    lc = advance(lc, s.len() + 1, word_size)?;
                     encode_stringz(s)
                 }
 
-                Token::Dir(Directive::Orig) => {
+                // Includes and imports left in the source were malformed
+                Token::Dir(Directive::Orig | Directive::Include | Directive::ImportSym) => {
                     return Err(Error::new(ErrorKind::SyntaxError));
                 }
 
                 Token::Dir(Directive::End) => break,
 
-                Token::Op(
-                    OpCode::Br
-                    | OpCode::Brn
-                    | OpCode::Brnp
-                    | OpCode::Brp
-                    | OpCode::Brz
-                    | OpCode::Brnz
-                    | OpCode::Brzp
-                    | OpCode::Brnzp,
-                ) => {
-                    let arg = token_iter.must_next()?;
-                    lc += 1;
-                    let offset = match arg {
-                        Token::Const(_) => arg.take_const()?,
-                        Token::Label(l) => {
-                            let addr = self
-                                .sym_table
-                                .get(l)
-                                .ok_or(Error::new(ErrorKind::MissingLabelError));
-                            addr?.wrapping_sub(lc)
-                        }
-                        _ => return Err(Error::new(ErrorKind::SyntaxError)),
-                    };
-                    encode_br(token, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Add) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let sr1 = token_iter.must_next()?.take_reg()?;
-                    let arg = token_iter.must_next()?;
-                    let bin = match arg {
-                        Token::Reg(_) => encode_add_reg(dr, sr1, arg.take_reg()?),
-                        Token::Const(_) => {
-                            encode_add_imm(dr, sr1, verify_offset(arg.take_const()?, 5)?)
-                        }
-                        _ => return Err(Error::new(ErrorKind::SyntaxError)),
-                    };
-                    lc += 1;
-                    bin
-                }
-
-                Token::Op(OpCode::And) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let sr1 = token_iter.must_next()?.take_reg()?;
-                    let arg = token_iter.must_next()?;
-                    let bin = match arg {
-                        Token::Reg(_) => encode_and_reg(dr, sr1, arg.take_reg()?),
-                        Token::Const(_) => {
-                            encode_and_imm(dr, sr1, verify_offset(arg.take_const()?, 5)?)
-                        }
-                        _ => return Err(Error::new(ErrorKind::SyntaxError)),
-                    };
-                    lc += 1;
-                    bin
-                }
-
-                Token::Op(OpCode::Jmp) => {
-                    let sr1 = token_iter.must_next()?.take_reg()?;
-                    lc += 1;
-                    encode_jmp(sr1)
+                // Declarations were collected by the first pass
+                Token::Dir(Directive::Global | Directive::Extern) => {
+                    token_iter.must_next()?.take_label()?;
+                    continue;
                 }
 
-                Token::Op(OpCode::Jsr) => {
+                Token::Op(op) if self.relaxed.contains(&token_idx) => {
                     let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_jsr(verify_offset(offset, 11)?)
-                }
-
-                Token::Op(OpCode::Jsrr) => {
-                    let sr1 = token_iter.must_next()?.take_reg()?;
-                    lc += 1;
-                    encode_jsrr(sr1)
-                }
-
-                Token::Op(OpCode::Ld) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_ld(dr, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Ldi) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_ldi(dr, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Ldr) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let baser = token_iter.must_next()?.take_reg()?;
-                    let offset = verify_offset(token_iter.must_next()?.take_const()?, 6)?;
-                    lc += 1;
-                    encode_ldr(dr, baser, offset)
-                }
-
-                Token::Op(OpCode::Lea) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_lea(dr, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Not) => {
-                    let dr = token_iter.must_next()?.take_reg()?;
-                    let sr = token_iter.must_next()?.take_reg()?;
-                    lc += 1;
-                    encode_not(dr, sr)
-                }
-
-                Token::Op(OpCode::Ret) => {
-                    lc += 1;
-                    encode_ret()
-                }
-
-                Token::Op(OpCode::Rti) => {
-                    lc += 1;
-                    encode_rti()
-                }
-
-                Token::Op(OpCode::St) => {
-                    let sr = token_iter.must_next()?.take_reg()?;
-                    let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_st(sr, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Sti) => {
-                    let sr = token_iter.must_next()?.take_reg()?;
-                    let label = token_iter.must_next()?.take_label()?;
-                    let addr = self
-                        .sym_table
-                        .get(&label)
-                        .ok_or(Error::new(ErrorKind::MissingLabelError))?;
-                    lc += 1;
-                    let offset = addr.wrapping_sub(lc);
-                    encode_sti(sr, verify_offset(offset, 9)?)
-                }
-
-                Token::Op(OpCode::Str) => {
-                    let sr1 = token_iter.must_next()?.take_reg()?;
-                    let sr2 = token_iter.must_next()?.take_reg()?;
-                    lc += 1;
-                    let offset = verify_offset(token_iter.must_next()?.take_const()?, 6)?;
-                    encode_str(sr1, sr2, offset)
-                }
-
-                Token::Op(OpCode::Trap) => {
-                    let trap_vec = token_iter.must_next()?.take_const()?;
-                    lc += 1;
-                    match trap_vec {
-                        0x20 => encode_getc(),
-                        0x21 => encode_out(),
-                        0x22 => encode_puts(),
-                        0x23 => encode_in(),
-                        0x24 => encode_putsp(),
-                        0x25 => encode_halt(),
-                        _ => return Err(Error::new(ErrorKind::SyntaxError)),
+                    let site = addr + relaxed_size(*op) - 1;
+                    let target =
+                        self.resolve(&label, site, RelocationKind::Abs16, &mut relocations)?;
+                    lc = advance(lc, relaxed_size(*op) as usize, word_size)?;
+                    match op {
+                        OpCode::Jsr => encode_far_jsr(target),
+                        _ => encode_far_br(*op, target),
                     }
                 }
 
-                Token::Op(OpCode::GetC) => {
-                    lc += 1;
-                    encode_getc()
-                }
-
-                Token::Op(OpCode::Puts) => {
-                    lc += 1;
-                    encode_puts()
-                }
-
-                Token::Op(OpCode::PutsP) => {
-                    lc += 1;
-                    encode_putsp()
-                }
-
-                Token::Op(OpCode::In) => {
-                    lc += 1;
-                    encode_in()
-                }
+                Token::Label(_) | Token::Op(OpCode::Res) => continue,
 
-                Token::Op(OpCode::Out) => {
-                    lc += 1;
-                    encode_out()
+                Token::Custom(mnemonic) => {
+                    let instruction = match self.options.extensions.find(mnemonic) {
+                        Some(instruction) if isa.has_reserved_opcode() => instruction.clone(),
+                        _ => {
+                            return Err(Error::with_message(
+                                ErrorKind::ParseOpCodeError,
+                                format!(
+                                    "{mnemonic} is a custom instruction, the {} target has no opcode for it",
+                                    isa.name()
+                                ),
+                            ))
+                        }
+                    };
+                    let mut resolve =
+                        |label: &str, kind| self.resolve(label, addr, kind, &mut relocations);
+                    let mut operands =
+                        Operands::new(&mut token_iter, addr, word_size, &mut resolve);
+                    let word = instruction.encode(&mut operands)?;
+                    lc = advance(lc, 1, word_size)?;
+                    vec![word]
                 }
 
-                Token::Op(OpCode::Halt) => {
-                    lc += 1;
-                    encode_halt()
+                Token::Op(op) => {
+                    let mut resolve =
+                        |label: &str, kind| self.resolve(label, addr, kind, &mut relocations);
+                    let mut operands =
+                        Operands::new(&mut token_iter, addr, word_size, &mut resolve);
+                    let bin = isa.encode(*op, &mut operands)?;
+                    lc = advance(lc, bin.len(), word_size)?;
+                    bin
                 }
 
-                Token::Label(_) | Token::Op(OpCode::Res) => continue,
-
                 // Orphan constants, registers or strings should be syntax error
                 Token::Str(_) | Token::Reg(_) | Token::Const(_) => {
                     return Err(Error::new(ErrorKind::SyntaxError))
@@ -445,24 +1344,107 @@ impl Assembler {
                 Token::Invalid => return Err(Error::new(ErrorKind::InvalidTokenError)),
             };
 
+            let kind = match token {
+                Token::Dir(Directive::Fill) => SpanKind::Fill,
+                Token::Dir(Directive::Blkw) => SpanKind::Blkw,
+                Token::Dir(Directive::Stringz) => SpanKind::Stringz,
+                _ => SpanKind::Code,
+            };
+            let location = self.origin(self.token_lines[token_idx]);
+            self.spans.push(SourceSpan {
+                file: location.file,
+                line: location.line,
+                column: self.statement_column(token_idx),
+                addr,
+                start: self.bin.len(),
+                len: bin.len(),
+                kind,
+            });
             self.bin.append(&mut bin);
         }
+        self.relocations = relocations;
 
+        self.line = 0;
         Ok(())
     }
 }
 
+/// BR and JSR may be rewritten into long-range sequences
+fn is_relaxable(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Br
+            | OpCode::Brn
+            | OpCode::Brz
+            | OpCode::Brp
+            | OpCode::Brnz
+            | OpCode::Brnp
+            | OpCode::Brzp
+            | OpCode::Brnzp
+            | OpCode::Jsr
+    )
+}
+
+/// Location counter after `words` words of `word_size` addresses each
+///
+/// Fails instead of wrapping when they run past the end of memory.
+fn advance(lc: u16, words: usize, word_size: u16) -> Result<u16> {
+    u16::try_from(words)
+        .ok()
+        .and_then(|words| words.checked_mul(word_size))
+        .and_then(|len| lc.checked_add(len))
+        .ok_or_else(|| {
+            Error::with_message(
+                ErrorKind::ValueError,
+                format!("{words} words at x{lc:04X} run past the end of memory"),
+            )
+        })
+}
+
+/// Word count of the long-range sequence emitted for a relaxed branch
+fn relaxed_size(op: OpCode) -> u16 {
+    match op {
+        OpCode::Brnzp => 3,
+        _ => 4,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::{env, io::BufReader};
 
     use super::*;
-    use byteorder::ReadBytesExt;
+    use crate::{
+        archive::Archive,
+        formats::read_obj,
+        linker::{LinkScript, Linked, Linker},
+        symbols::{read_sym, write_sym},
+    };
+    use byteorder::{BigEndian, ReadBytesExt};
+
+    /// Outputs go to a scratch directory under the system temp dir
+    fn test_output(stem: &str) -> OutputPaths {
+        OutputPaths {
+            out_dir: Some(env::temp_dir().join("assembler-tests")),
+            ..OutputPaths::new(stem)
+        }
+    }
+
+    /// Link an object against the standard library
+    fn link_stdlib(object: Object) -> Linked {
+        let mut linker = Linker::new(LinkScript::default());
+        linker.add_object(object);
+        linker.add_archive(Archive::from_path(Path::new("lib/stdlib.lib")).unwrap());
+        linker.link().unwrap()
+    }
 
     #[test]
     fn test_assembler_basic() {
-        let mut test_ass =
-            Assembler::new(PathBuf::from("asm/test.ggnm"), String::from("test"), true);
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/test.ggnm"),
+            test_output("test"),
+            AssemblerOptions::builder().debug(true).build(),
+        );
 
         let res = test_ass.read_file();
         assert!(res.is_ok());
@@ -475,8 +1457,7 @@ mod tests {
         let res = test_ass.emit_sym_table();
         assert!(res.is_ok());
 
-        let mut sym_path = resolve_dir();
-        sym_path.push("test.sym");
+        let sym_path = test_output("test").resolve("sym").unwrap();
         let f = File::open(sym_path);
         assert!(f.is_ok());
         let mut f = f.unwrap();
@@ -507,8 +1488,8 @@ mod tests {
     fn test_assembler_instructions() {
         let mut test_ass = Assembler::new(
             PathBuf::from("asm/instructions.ggnm"),
-            String::from("instructions"),
-            false,
+            test_output("instructions"),
+            AssemblerOptions::default(),
         );
 
         let res = test_ass.read_file();
@@ -530,8 +1511,7 @@ mod tests {
         let res = test_ass.emit_sym_table();
         assert!(res.is_ok());
 
-        let mut sym_path = resolve_dir();
-        sym_path.push("instructions.sym");
+        let sym_path = test_output("instructions").resolve("sym").unwrap();
         let f = File::open(sym_path);
         assert!(f.is_ok());
         let mut f = f.unwrap();
@@ -560,19 +1540,21 @@ mod tests {
 
     #[test]
     fn test_assembler_2048() {
-        let mut test_ass =
-            Assembler::new(PathBuf::from("asm/2048.ggnm"), String::from("2048"), false);
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/2048.ggnm"),
+            test_output("2048"),
+            AssemblerOptions::builder().relocatable(true).build(),
+        );
 
         let res = test_ass.assemble();
         assert!(res.is_ok());
 
-        let mut sym_path = resolve_dir();
-        sym_path.push("2048.sym");
-        let f = File::open(sym_path);
-        assert!(f.is_ok());
-        let mut f = f.unwrap();
-        let mut sym_file_content = String::new();
-        f.read_to_string(&mut sym_file_content).unwrap();
+        // DIVIDE and MULTIPLY come from the standard library
+        let rel_path = test_output("2048").resolve("rel").unwrap();
+        let linked = link_stdlib(Object::from_path(&rel_path).unwrap());
+        let mut sym_file_content = Vec::new();
+        write_sym(&mut sym_file_content, &linked.symbols).unwrap();
+        let sym_file_content = String::from_utf8(sym_file_content).unwrap();
 
         let f = File::open("roms/2048.sym");
         assert!(f.is_ok());
@@ -588,7 +1570,271 @@ mod tests {
             expected.push(word);
         }
 
-        assert_eq!(&test_ass.bin[..], &expected[..]);
+        assert_eq!(&linked.image[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_assembler_from_source() {
+        let mut source = String::new();
+        File::open("asm/test.ggnm")
+            .unwrap()
+            .read_to_string(&mut source)
+            .unwrap();
+
+        let mut test_ass = Assembler::from_source(
+            &source,
+            test_output("-"),
+            AssemblerOptions::builder().name("submission.asm").build(),
+        );
+        assert!(test_ass.build().is_ok());
+
+        let mut file = BufReader::new(File::open("roms/test.obj").unwrap());
+        let mut expected: Vec<u16> = vec![];
+        while let Ok(word) = file.read_u16::<BigEndian>() {
+            expected.push(word);
+        }
+
+        assert_eq!(test_ass.bin(), &expected[..]);
+
+        // Building again starts from an empty image
+        let spans = test_ass.source_map().len();
+        assert!(test_ass.build().is_ok());
+        assert_eq!(test_ass.bin(), &expected[..]);
+        assert_eq!(test_ass.source_map().len(), spans);
+    }
+
+    #[test]
+    fn test_assembler_listing() {
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/test.ggnm"),
+            test_output("test"),
+            AssemblerOptions::builder().listing(true).build(),
+        );
+        test_ass.read_file().unwrap();
+        test_ass.resolve_layout().unwrap();
+        test_ass.second_pass().unwrap();
+
+        let mut listing = Vec::new();
+        let res = test_ass.write_listing(&mut listing);
+        assert!(res.is_ok());
+
+        let mut expected = String::new();
+        File::open("roms/test.lst")
+            .unwrap()
+            .read_to_string(&mut expected)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(listing).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_assembler_relax() {
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/relax.ggnm"),
+            test_output("relax"),
+            AssemblerOptions::default(),
+        );
+        test_ass.read_file().unwrap();
+        test_ass.resolve_layout().unwrap();
+        let res = test_ass.second_pass().map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::ValueError));
+
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/relax.ggnm"),
+            test_output("relax"),
+            AssemblerOptions::builder().relax(true).build(),
+        );
+        test_ass.read_file().unwrap();
+        test_ass.resolve_layout().unwrap();
+        assert!(test_ass.second_pass().is_ok());
+
+        assert_eq!(test_ass.sym_table.get("NEAR"), Some(&0x310b));
+        assert_eq!(test_ass.sym_table.get("FAR"), Some(&0x350c));
+        assert_eq!(test_ass.sym_table.get("SUB"), Some(&0x350d));
+
+        let relaxed: Vec<_> = test_ass.relaxations().iter().map(|r| r.line).collect();
+        assert_eq!(relaxed, vec![5, 6, 7, 8]);
+
+        assert_eq!(
+            &test_ass.bin[..16],
+            &[
+                0x3000, // .ORIG
+                0x0603, 0x2e01, 0xc1c0, 0x310b, // BRn NEAR
+                0x0a03, 0x2e01, 0xc1c0, 0x350c, // BRz FAR
+                0x2e02, 0x41c0, 0x0e01, 0x350d, // JSR SUB
+                0x2e01, 0xc1c0, 0x350c, // BRnzp FAR
+            ]
+        );
+        assert_eq!(test_ass.bin.len(), 0x350e - 0x3000 + 1);
+    }
+
+    #[test]
+    fn test_assembler_include() {
+        let mut test_ass = Assembler::new(
+            PathBuf::from("asm/include.ggnm"),
+            test_output("include"),
+            AssemblerOptions::default(),
+        );
+        assert!(test_ass.build().is_ok());
+
+        let mut file = BufReader::new(File::open("roms/test.obj").unwrap());
+        let mut expected: Vec<u16> = vec![];
+        while let Ok(word) = file.read_u16::<BigEndian>() {
+            expected.push(word);
+        }
+        assert_eq!(test_ass.bin(), &expected[..]);
+
+        assert_eq!(test_ass.files().len(), 2);
+        let span = test_ass.source_map().last().unwrap();
+        assert_eq!((span.file, span.line, span.kind), (1, 2, SpanKind::Stringz));
+
+        // Include paths are searched after the including file's directory
+        let source = ".ORIG x3000\nLEA R0, HELLO_WORLD\n.INCLUDE \"hello.inc\"\n.END\n";
+        let mut test_ass = Assembler::from_source(source, test_output("-"), Default::default());
+        let res = test_ass.build().map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::IOError));
+
+        let options = AssemblerOptions::builder()
+            .include_path("asm/include")
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        assert!(test_ass.build().is_ok());
+        assert_eq!(test_ass.sym_table().get("HELLO_WORLD"), Some(&0x3001));
+    }
+
+    #[test]
+    fn test_assembler_import_symbols() {
+        let source = "\
+.ORIG x0440
+.IMPORTSYM \"os.sym\"
+    JSR OS_PUTS
+    LDI R0, KBSR_PTR
+    HALT
+KBSR_PTR .FILL KBSR
+.END
+";
+        let options = AssemblerOptions::builder()
+            .include_path("asm/include")
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        assert!(test_ass.build().is_ok());
+        assert_eq!(test_ass.bin(), &[0x0440, 0x480f, 0xa001, 0xf025, 0xfe00]);
+        assert!(!test_ass.sym_table().contains_key("OS_PUTS"));
+
+        // Imported addresses outside a PC-relative field's reach are rejected
+        let source = ".ORIG x3000\nLD R0, KBSR\nHALT\n.END\n";
+        let options = AssemblerOptions::builder()
+            .symbol_file("asm/include/os.sym")
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        let error = test_ass.build().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[ValueError] imported label KBSR at xFE00 is out of range of the 9-bit PC offset at x3000, load its address with .FILL"
+        );
+    }
+
+    #[test]
+    fn test_assembler_options() {
+        let source = "loop ADD R0, R0, #-1\nBR loop\nLD R1, stack\n.END\n";
+        let options = AssemblerOptions::builder()
+            .dialect(Dialect::Lc3as)
+            .case_sensitive(false)
+            .default_origin(0x3000)
+            .define("Stack", 0x3010)
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        assert!(test_ass.build().is_ok());
+
+        assert_eq!(test_ass.bin(), &[0x3000, 0x103f, 0x0ffe, 0x220d]);
+        assert_eq!(test_ass.sym_table().get("LOOP"), Some(&0x3000));
+        assert!(!test_ass.sym_table().contains_key("STACK"));
+
+        let mut test_ass = Assembler::from_source(source, test_output("-"), Default::default());
+        let res = test_ass.build().map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::SyntaxError));
+    }
+
+    #[test]
+    fn test_assembler_lints() {
+        let source = ".ORIG x3000\nSTART HALT\nUNUSED .FILL START\n.END\n";
+        let options = AssemblerOptions::builder()
+            .lint(Lint::UnusedLabel, LintLevel::Warn)
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        assert!(test_ass.build().is_ok());
+
+        let warnings: Vec<_> = test_ass.warnings().iter().map(|w| w.line).collect();
+        assert_eq!(warnings, vec![3]);
+        assert_eq!(
+            test_ass.warnings()[0].to_string(),
+            "<source>:3: [unused-label] label UNUSED is never used"
+        );
+
+        let options = AssemblerOptions::builder()
+            .lint(Lint::UnusedLabel, LintLevel::Deny)
+            .build();
+        let mut test_ass = Assembler::from_source(source, test_output("-"), options);
+        let res = test_ass.build().map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::LintError));
+    }
+
+    /// Every program under asm/ against its image and symbols in roms/
+    #[test]
+    fn test_assembler_golden() {
+        for entry in fs::read_dir("asm").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "ggnm") {
+                continue;
+            }
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+
+            // Sources importing labels are linked against the standard library
+            let options = AssemblerOptions::builder().relax(true).build();
+            let relocatable = AssemblerOptions {
+                relocatable: true,
+                ..options.clone()
+            };
+            let mut test_ass = Assembler::new(path.clone(), test_output(stem), relocatable);
+            assert!(test_ass.build().is_ok(), "{stem} does not assemble");
+            let object = test_ass.object().unwrap();
+            let (image, sym_table) = if object.imports.is_empty() {
+                let mut test_ass = Assembler::new(path.clone(), test_output(stem), options);
+                assert!(test_ass.build().is_ok(), "{stem} does not assemble");
+                (test_ass.bin, test_ass.sym_table)
+            } else {
+                let linked = link_stdlib(object);
+                (linked.image, linked.symbols)
+            };
+
+            let expected = read_obj(File::open(format!("roms/{stem}.obj")).unwrap()).unwrap();
+            assert_eq!(image, expected, "{stem} image differs");
+
+            let symbols = read_sym(File::open(format!("roms/{stem}.sym")).unwrap()).unwrap();
+            assert_eq!(sym_table, symbols, "{stem} symbols differ");
+        }
+    }
+
+    #[test]
+    fn test_relax_rom() {
+        // Each far branch is `BR(!nzp) #3; LD R7, #1; JMP R7; .FILL addr`
+        // and a far JSR is `LD R7, #2; JSRR R7; BRnzp #1; .FILL addr`
+        let image = read_obj(File::open("roms/relax.obj").unwrap()).unwrap();
+        #[rustfmt::skip]
+        let code = [
+            0x3000,
+            0x0603, 0x2e01, 0xc1c0, 0x310b, // BRn NEAR, inverted to BRzp
+            0x0a03, 0x2e01, 0xc1c0, 0x350c, // BRz FAR, inverted to BRnp
+            0x2e02, 0x41c0, 0x0e01, 0x350d, // JSR SUB
+            0x2e01, 0xc1c0, 0x350c,         // BRnzp FAR, nothing to invert
+        ];
+        assert_eq!(image[..code.len()], code);
+        assert_eq!(image.len(), 1 + 0x350e - 0x3000);
+        assert_eq!(image[1 + 0x310b - 0x3000], 0xf025); // NEAR HALT
+        assert_eq!(image[1 + 0x350c - 0x3000..], [0xf025, 0xc1c0]); // FAR HALT, SUB RET
+        assert!(image[code.len()..1 + 0x310b - 0x3000]
+            .iter()
+            .all(|w| *w == 0));
     }
 
     #[test]
@@ -597,8 +1843,8 @@ mod tests {
         for i in 1..=syntax_tests {
             let mut test_ass = Assembler::new(
                 PathBuf::from(format!("asm/fault_tests/syntax_fault-{i}.ggnm")),
-                format!("syntax_fault-{i}"),
-                false,
+                test_output(&format!("syntax_fault-{i}")),
+                AssemblerOptions::default(),
             );
             let res = test_ass.assemble().map_err(|e| e.kind);
             assert_eq!(res, Err(ErrorKind::SyntaxError));
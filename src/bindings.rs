@@ -0,0 +1,165 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    symbols::sorted_symbols,
+};
+
+/// Languages the symbol table is exported to for host tools
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BindingFormat {
+    /// A header of `#define` constants (.h)
+    C,
+    /// A module of `pub const` items (.rs)
+    Rust,
+    /// An object mapping labels to addresses (.json)
+    Json,
+}
+
+impl BindingFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BindingFormat::C => "h",
+            BindingFormat::Rust => "rs",
+            BindingFormat::Json => "json",
+        }
+    }
+}
+
+/// Turn a label into an upper case identifier of C and Rust
+///
+/// Characters outside `[A-Za-z0-9_]` become `_` and a leading digit is
+/// prefixed with `_`. Upper case keeps clear of both languages' keywords.
+pub fn identifier(label: &str) -> String {
+    let mut name: String = label
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Labels in address order with their identifiers, failing when two labels share one
+fn identifiers(symbols: &HashMap<String, u16>) -> Result<Vec<(String, u16)>> {
+    let mut labels: HashMap<String, &str> = HashMap::new();
+    let mut constants = Vec::new();
+    for (label, addr) in sorted_symbols(symbols) {
+        let name = identifier(label);
+        if let Some(other) = labels.insert(name.clone(), label) {
+            let (a, b) = if other < label {
+                (other, label)
+            } else {
+                (label, other)
+            };
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                format!("labels {a} and {b} both export as {name}"),
+            ));
+        }
+        constants.push((name, addr));
+    }
+    Ok(constants)
+}
+
+/// Write the symbol table as constants, `name` is the program they describe
+pub fn write_bindings<W: Write>(
+    w: &mut W,
+    format: BindingFormat,
+    name: &str,
+    symbols: &HashMap<String, u16>,
+) -> Result<()> {
+    match format {
+        BindingFormat::C => {
+            let guard = format!("{}_SYMBOLS_H", identifier(name));
+            writeln!(w, "/* Symbols of {name}, generated by the assembler */")?;
+            writeln!(w, "#ifndef {guard}")?;
+            writeln!(w, "#define {guard}")?;
+            writeln!(w)?;
+            for (constant, addr) in identifiers(symbols)? {
+                writeln!(w, "#define {constant} 0x{addr:04X}")?;
+            }
+            writeln!(w)?;
+            writeln!(w, "#endif")?;
+        }
+        BindingFormat::Rust => {
+            writeln!(w, "//! Symbols of {name}, generated by the assembler")?;
+            writeln!(w)?;
+            for (constant, addr) in identifiers(symbols)? {
+                writeln!(w, "pub const {constant}: u16 = 0x{addr:04X};")?;
+            }
+        }
+        BindingFormat::Json => {
+            let ordered: BTreeMap<_, _> = symbols.iter().collect();
+            let json = serde_json::to_string_pretty(&ordered).expect("symbols serialize");
+            writeln!(w, "{json}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_bindings() {
+        let symbols = HashMap::from([
+            ("HELLO_WORLD".to_owned(), 0x3003),
+            ("loop.2".to_owned(), 0x3001),
+            ("1ST".to_owned(), 0x3000),
+        ]);
+        let write = |format| {
+            let mut out = Vec::new();
+            write_bindings(&mut out, format, "hello.asm", &symbols).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            write(BindingFormat::C),
+            "\
+/* Symbols of hello.asm, generated by the assembler */
+#ifndef HELLO_ASM_SYMBOLS_H
+#define HELLO_ASM_SYMBOLS_H
+
+#define _1ST 0x3000
+#define LOOP_2 0x3001
+#define HELLO_WORLD 0x3003
+
+#endif
+"
+        );
+        assert_eq!(
+            write(BindingFormat::Rust),
+            "\
+//! Symbols of hello.asm, generated by the assembler
+
+pub const _1ST: u16 = 0x3000;
+pub const LOOP_2: u16 = 0x3001;
+pub const HELLO_WORLD: u16 = 0x3003;
+"
+        );
+        let json: HashMap<String, u16> = serde_json::from_str(&write(BindingFormat::Json)).unwrap();
+        assert_eq!(json, symbols);
+
+        let symbols = HashMap::from([("loop".to_owned(), 0x3000), ("LOOP".to_owned(), 0x3001)]);
+        let error =
+            write_bindings(&mut Vec::new(), BindingFormat::Rust, "x", &symbols).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[ValueError] labels LOOP and loop both export as LOOP"
+        );
+    }
+}
@@ -1,24 +1,389 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+
+use assembler::{
+    bindings::BindingFormat,
+    formats::{Endian, OutputFormat},
+    lint::{Lint, LintLevel},
+    manifest::MANIFEST_NAME,
+    options::{AssemblerOptions, AssemblerOptionsBuilder, Dialect},
+    simulator::DEFAULT_LIMIT,
+    symbols::SymbolFormat,
+    target::Isa,
+    utils::{parse_constant, OutputPaths},
+};
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Without a subcommand, assemble a single file like `build --file`
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Assemble a file, or every source of a project manifest without --file
+    Build(BuildArgs),
+
+    /// Assemble and resolve symbols without writing any output
+    Check(BuildArgs),
+
+    /// Reformat source files in place
+    Fmt(FmtArgs),
+
+    /// Turn an object image back into source
+    Disasm(DisasmArgs),
+
+    /// Assemble a program and execute it in the simulator
+    Run(RunArgs),
+
+    /// Run the test cases of TOML specs against object images
+    Test(TestArgs),
+
+    /// Step through a program in the simulator at the source level
+    Debug(DebugArgs),
+
+    /// Combine relocatable objects into an object image and symbol table
+    Link(LinkArgs),
+
+    /// Bundle relocatable objects into a static library
+    Archive(ArchiveArgs),
+}
+
+#[derive(Args)]
+pub struct BuildArgs {
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+
+    /// Path to the project manifest
+    #[arg(short, long, default_value = MANIFEST_NAME, conflicts_with = "file")]
+    pub manifest: PathBuf,
+
+    /// Target whose settings override the manifest's options
+    #[arg(short, long, conflicts_with = "file")]
+    pub target: Option<String>,
+}
+
+#[derive(Args)]
+pub struct FmtArgs {
+    /// Source files to format, `-` formats stdin to stdout
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Only report files that are not formatted
+    #[arg(long, default_value_t = false)]
+    pub check: bool,
+}
+
+#[derive(Args)]
+pub struct DisasmArgs {
+    /// Object image to disassemble, `-` reads stdin
+    pub file: PathBuf,
+
+    /// Symbol table whose labels name addresses in the output
+    #[arg(short, long)]
+    pub sym: Option<PathBuf>,
+
+    /// Path of the source to write, defaults to stdout
+    #[arg(short, long)]
+    pub outfile: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Source file to run, or an object image ending in .obj; `-` reads source from stdin
+    pub file: PathBuf,
+
+    /// File the program reads its input from instead of stdin
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+
+    /// Number of instructions executed before the program is stopped
+    #[arg(long, default_value_t = DEFAULT_LIMIT)]
+    pub limit: u64,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// Test specs to run
+    #[arg(required = true)]
+    pub specs: Vec<PathBuf>,
+
+    /// Also write the results as JUnit XML, `-` for stdout
+    #[arg(long)]
+    pub junit: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct DebugArgs {
+    /// Source file to debug
+    pub file: PathBuf,
+
+    /// File the program reads its input from, no input is available without one
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+#[derive(Args)]
+pub struct LinkArgs {
+    /// Relocatable objects to link, sections are laid out in this order
+    ///
+    /// Files ending in .lib are archives, only the members resolving an import are linked
+    #[arg(required = true)]
+    pub objects: Vec<PathBuf>,
+
+    /// Output path (without extension)
+    ///
+    /// Linker emits <OUTFILE>.obj and <OUTFILE>.sym. Defaults to the name of the first object
+    #[arg(short, long)]
+    pub outfile: Option<PathBuf>,
+
+    /// TOML linker script placing sections
+    #[arg(short = 'T', long)]
+    pub script: Option<PathBuf>,
+
+    /// Address sections without a placement are laid out from [default: x3000]
+    #[arg(long, value_parser = parse_address)]
+    pub origin: Option<u16>,
+
+    /// Place a section, as OBJECT[:SECTION]=ADDR; may be repeated
+    #[arg(long = "section", value_parser = parse_define)]
+    pub sections: Vec<(String, u16)>,
+
+    /// Symbol table formats to emit, lc3as replaces the native .sym layout and excludes sym
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sym")]
+    pub sym_format: Vec<SymbolFormat>,
+
+    /// Export the symbols as constants for host tools, each as <OUTFILE>.<ext>
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub emit_symbols: Vec<BindingFormat>,
+
+    /// Emit <OUTFILE>.map with section placement, free gaps, symbol references and code/data sizes
+    #[arg(long, default_value_t = false)]
+    pub map: bool,
+}
+
+#[derive(Args)]
+pub struct ArchiveArgs {
+    /// Relocatable objects to bundle
+    #[arg(required = true)]
+    pub objects: Vec<PathBuf>,
+
+    /// Path of the archive to write, usually ending in .lib
+    #[arg(short, long)]
+    pub outfile: PathBuf,
+}
+
+/// Settings for assembling a single file
+#[derive(Args)]
+pub struct AssembleArgs {
     /// Path to source assembly file
     ///
     /// Input file extensions should generally be .asm or .ggnm,
-    /// but it's not strictly checked
+    /// but it's not strictly checked. `-` reads from stdin
     #[arg(short, long)]
-    pub file: PathBuf,
+    pub file: Option<PathBuf>,
 
-    /// Output file name (without extension)
+    /// Output path (without extension)
     ///
-    /// Assembler emits <OUTFILE>.obj and <OUTFILE>.sym
-    #[arg(short, long, default_value_t = String::from("out"))]
-    pub outfile: String,
+    /// Assembler emits <OUTFILE>.obj and <OUTFILE>.sym (and <OUTFILE>.lst with --listing).
+    /// Defaults to the input file name, `-` writes the only output, one --format, to stdout
+    #[arg(short, long)]
+    pub outfile: Option<PathBuf>,
+
+    /// Directory for outputs named after <OUTFILE>
+    #[arg(long)]
+    pub out_dir: Option<PathBuf>,
+
+    /// Path of the object image, `-` for stdout
+    #[arg(long)]
+    pub obj: Option<PathBuf>,
+
+    /// Path of the symbol table, `-` for stdout
+    #[arg(long)]
+    pub sym: Option<PathBuf>,
 
     /// Turn on debug-mode
     #[arg(short, long, default_value_t = false)]
     pub debug: bool,
+
+    /// Output formats to emit, each as <OUTFILE>.<ext>
+    #[arg(
+        short = 'F',
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "obj"
+    )]
+    pub format: Vec<OutputFormat>,
+
+    /// Symbol table formats to emit, lc3as replaces the native .sym layout and excludes sym
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sym")]
+    pub sym_format: Vec<SymbolFormat>,
+
+    /// Export the symbols as constants for host tools, each as <OUTFILE>.<ext>
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub emit_symbols: Vec<BindingFormat>,
+
+    /// Byte order of raw binary output
+    #[arg(long, value_enum, default_value_t = Endian::Big)]
+    pub endian: Endian,
+
+    /// Emit a listing file with addresses, encoded words and source
+    #[arg(short, long, default_value_t = false)]
+    pub listing: bool,
+
+    /// Emit <OUTFILE>.dbg.json with the file, line and column of every word
+    #[arg(short = 'g', long, default_value_t = false)]
+    pub debug_info: bool,
+
+    /// Emit a relocatable object <OUTFILE>.rel for the linker instead of an image
+    ///
+    /// .ORIG is optional, .GLOBAL exports a label and .EXTERN imports one
+    #[arg(short = 'c', long, default_value_t = false)]
+    pub relocatable: bool,
+
+    /// Emit <OUTFILE>.map with section placement, free gaps, symbol references and code/data sizes
+    #[arg(long, default_value_t = false)]
+    pub map: bool,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+}
+
+/// Settings that change how source is read, shared by every command that assembles
+#[derive(Args)]
+pub struct SourceArgs {
+    /// Instruction set the source is written for
+    #[arg(long, value_enum, default_value_t = Isa::Lc3)]
+    pub isa: Isa,
+
+    /// Rewrite out-of-range BR and JSR into long-range sequences
+    ///
+    /// Relaxed branches jump through R7, which is clobbered
+    #[arg(short, long, default_value_t = false)]
+    pub relax: bool,
+
+    /// Source dialect, lc3as treats a plain BR as BRnzp
+    #[arg(long, value_enum, default_value_t = Dialect::Native)]
+    pub dialect: Dialect,
+
+    /// Directory searched by .INCLUDE, may be repeated
+    #[arg(short = 'I', long = "include")]
+    pub include_paths: Vec<PathBuf>,
+
+    /// Symbol table whose labels are usable as absolute addresses, may be repeated
+    #[arg(long = "symbols")]
+    pub symbol_files: Vec<PathBuf>,
+
+    /// Constant usable wherever a label is, as NAME=VALUE
+    #[arg(short = 'D', long = "define", value_parser = parse_define)]
+    pub defines: Vec<(String, u16)>,
+
+    /// Report a lint as a warning
+    #[arg(short = 'W', long, value_enum, value_delimiter = ',')]
+    pub warn: Vec<Lint>,
+
+    /// Fail the assembly on a lint
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub deny: Vec<Lint>,
+
+    /// Treat labels as case insensitive
+    #[arg(long, default_value_t = false)]
+    pub ignore_case: bool,
+
+    /// Origin assumed when the source has no .ORIG
+    #[arg(long, value_parser = parse_address)]
+    pub origin: Option<u16>,
+}
+
+impl AssembleArgs {
+    /// Source file given with `--file`
+    pub fn file(&self) -> &Path {
+        self.file.as_deref().unwrap_or(Path::new("-"))
+    }
+
+    /// Assembler settings selected on the command line
+    pub fn options(&self) -> AssemblerOptions {
+        self.source
+            .builder(self.file())
+            .output_formats(self.format.iter().copied())
+            .symbol_formats(self.sym_format.iter().copied())
+            .bindings(self.emit_symbols.iter().copied())
+            .endian(self.endian)
+            .listing(self.listing)
+            .debug_info(self.debug_info)
+            .relocatable(self.relocatable)
+            .map(self.map)
+            .debug(self.debug)
+            .build()
+    }
+
+    /// Output destinations selected on the command line
+    pub fn output_paths(&self) -> OutputPaths {
+        let stem = match &self.outfile {
+            Some(stem) => OutputPaths::new(stem),
+            None => OutputPaths::from_input(self.file()),
+        };
+        OutputPaths {
+            out_dir: self.out_dir.clone(),
+            obj: self.obj.clone(),
+            sym: self.sym.clone(),
+            ..stem
+        }
+    }
+}
+
+impl SourceArgs {
+    /// Builder holding the source settings, named after `file`
+    pub fn builder(&self, file: &Path) -> AssemblerOptionsBuilder {
+        let mut builder = AssemblerOptions::builder()
+            .name(file.display().to_string())
+            .isa(self.isa)
+            .dialect(self.dialect)
+            .case_sensitive(!self.ignore_case)
+            .relax(self.relax);
+        for path in &self.include_paths {
+            builder = builder.include_path(path);
+        }
+        for path in &self.symbol_files {
+            builder = builder.symbol_file(path);
+        }
+        for (name, value) in &self.defines {
+            builder = builder.define(name, *value);
+        }
+        for lint in &self.warn {
+            builder = builder.lint(*lint, LintLevel::Warn);
+        }
+        for lint in &self.deny {
+            builder = builder.lint(*lint, LintLevel::Deny);
+        }
+        if let Some(origin) = self.origin {
+            builder = builder.default_origin(origin);
+        }
+        builder
+    }
+}
+
+/// Parse `NAME=VALUE`, the value written like an assembly constant
+fn parse_define(s: &str) -> Result<(String, u16), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{s}`"))?;
+    Ok((name.to_owned(), parse_address(value)?))
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    parse_constant(s).map_err(|e| e.to_string())
 }
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::SpanKind,
+    error::{Error, ErrorKind, Result},
+    Program,
+};
+
+/// Format version written to `version`, raised on incompatible changes
+pub const DEBUG_INFO_VERSION: u32 = 1;
+
+/// Source location of every word in an image, written as `<OUTFILE>.dbg.json`
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "files": [
+///     { "path": "asm/include.ggnm" },
+///     { "path": "asm/include/hello.inc", "included-from": { "file": 0, "line": 5 } }
+///   ],
+///   "words": [
+///     { "addr": 12288, "file": 0, "line": 2, "column": 1, "kind": "code" },
+///     { "addr": 12291, "file": 1, "line": 2, "column": 13, "kind": "data" }
+///   ]
+/// }
+/// ```
+///
+/// `file` indexes `files`, lines and columns count from 1 and the column is
+/// that of the statement after any label. `words` is in address order and
+/// covers the whole image but its origin. A word from an included file
+/// finds the `.INCLUDE` line through `included-from`, which nested includes
+/// chain.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DebugInfo {
+    pub version: u32,
+    pub files: Vec<SourceFile>,
+    pub words: Vec<WordInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceFile {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub included_from: Option<Location>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct WordInfo {
+    pub addr: u16,
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub kind: WordKind,
+}
+
+/// Whether a word was written as an instruction or by a data directive
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WordKind {
+    Code,
+    Data,
+}
+
+impl DebugInfo {
+    pub fn from_program(program: &Program) -> Self {
+        let files = program
+            .files()
+            .iter()
+            .zip(program.included_from())
+            .map(|(path, origin)| SourceFile {
+                path: path.display().to_string(),
+                included_from: origin.map(|origin| Location {
+                    file: origin.file,
+                    line: origin.line,
+                }),
+            })
+            .collect();
+
+        let word_size = program.word_size();
+        let mut words: Vec<_> = program
+            .source_map()
+            .iter()
+            .filter(|span| span.kind != SpanKind::Origin)
+            .flat_map(|span| {
+                let kind = match span.kind {
+                    SpanKind::Code => WordKind::Code,
+                    _ => WordKind::Data,
+                };
+                (0..span.len).map(move |i| WordInfo {
+                    addr: span.addr.wrapping_add(i as u16 * word_size),
+                    file: span.file,
+                    line: span.line,
+                    column: span.column,
+                    kind,
+                })
+            })
+            .collect();
+        words.sort_by_key(|word| word.addr);
+
+        Self {
+            version: DEBUG_INFO_VERSION,
+            files,
+            words,
+        }
+    }
+
+    /// Location of the word at `addr`
+    pub fn word(&self, addr: u16) -> Option<&WordInfo> {
+        let i = self.words.binary_search_by_key(&addr, |w| w.addr).ok()?;
+        self.words.get(i)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("debug info serializes") + "\n"
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        serde_json::from_str(text)
+            .map_err(|e| Error::with_message(ErrorKind::ValueError, e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, target::Isa, utils::OutputPaths, Assembler, AssemblerOptions};
+
+    #[test]
+    fn test_debug_info() {
+        let mut asm = Assembler::new(
+            "asm/include.ggnm".into(),
+            OutputPaths::default(),
+            AssemblerOptions::default(),
+        );
+        asm.build().unwrap();
+        let info = DebugInfo::from_program(&Program::from_assembler(&asm));
+
+        assert_eq!(
+            info.files[1],
+            SourceFile {
+                path: String::from("asm/include/hello.inc"),
+                included_from: Some(Location { file: 0, line: 5 }),
+            }
+        );
+        assert_eq!(info.words.len(), 16);
+        assert_eq!(
+            info.words[0],
+            WordInfo {
+                addr: 0x3000,
+                file: 0,
+                line: 2,
+                column: 1,
+                kind: WordKind::Code,
+            }
+        );
+        assert_eq!(
+            info.word(0x3004),
+            Some(&WordInfo {
+                addr: 0x3004,
+                file: 1,
+                line: 2,
+                column: 13,
+                kind: WordKind::Data,
+            })
+        );
+        assert_eq!(info.word(0x3010), None);
+
+        let json = info.to_json();
+        assert!(json.contains("\"included-from\": {\n        \"file\": 0,"));
+        assert_eq!(DebugInfo::from_json(&json).unwrap(), info);
+    }
+
+    #[test]
+    fn test_debug_info_lc3b() {
+        let options = AssemblerOptions::builder().isa(Isa::Lc3b).build();
+        let source = ".ORIG x3000\nADD R0, R0, #1\nS .STRINGZ \"ab\"\n.END\n";
+        let program = assemble(source, &options).unwrap();
+        let info = DebugInfo::from_program(&program);
+
+        let addrs: Vec<_> = info.words.iter().map(|word| word.addr).collect();
+        assert_eq!(addrs, vec![0x3000, 0x3002, 0x3004, 0x3006]);
+        assert_eq!(info.word(0x3006).map(|word| word.line), Some(3));
+        assert_eq!(program.line_of(0x3004), Some(3));
+        assert_eq!(program.line_of(0x3008), None);
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use num_traits::FromPrimitive;
+
+use crate::{
+    enums::Register,
+    error::{Error, ErrorKind, Result},
+    instruction::Instruction,
+    simulator::{Simulator, Stop, DEFAULT_LIMIT},
+    utils::parse_constant,
+    Program,
+};
+
+/// Commands understood by [`Debugger::execute`]
+pub const HELP: &str = "\
+break LABEL|ADDR|FILE:LINE  stop before the instruction there
+delete LABEL|ADDR           remove a breakpoint
+watch LABEL|ADDR            stop when the word there changes
+continue                    run to the next breakpoint, watch or HALT
+step                        execute one instruction
+next                        like step, but run called subroutines to their return
+finish                      run until the current subroutine returns
+regs                        show registers and condition codes
+mem ADDR [COUNT]            show COUNT words from ADDR
+disasm [ADDR] [COUNT]       disassemble COUNT words from ADDR, the PC by default
+quit                        leave the debugger";
+
+/// Why execution paused
+enum Pause {
+    Stopped(Stop),
+    Stepped,
+    Returned,
+    Watch { addr: u16, old: u16, new: u16 },
+}
+
+/// Source-level debugger driving a [`Simulator`]
+///
+/// Each command returns the text to show, every stop names the source line
+/// at the PC using the program's source map.
+pub struct Debugger<R, W> {
+    program: Program,
+    sim: Simulator<R, W>,
+    /// Lines of each file in [`Program::files`]
+    sources: Vec<Vec<String>>,
+    labels: HashMap<u16, String>,
+    /// Watched addresses and the value last seen there
+    watches: BTreeMap<u16, u16>,
+    /// Instructions a single command may execute
+    limit: u64,
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    /// Load `program`, reading its source files to show lines at each stop
+    pub fn new(program: Program, input: R, output: W) -> Result<Self> {
+        let mut sim = Simulator::new(input, output);
+        sim.load(&program.image())?;
+
+        let sources = program
+            .files()
+            .iter()
+            .map(|path| {
+                fs::read_to_string(path)
+                    .map(|text| text.lines().map(str::to_owned).collect())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut labels = HashMap::new();
+        let mut symbols: Vec<_> = program.symbols().iter().collect();
+        symbols.sort();
+        for (name, addr) in symbols {
+            labels.entry(*addr).or_insert_with(|| name.clone());
+        }
+
+        Ok(Self {
+            program,
+            sim,
+            sources,
+            labels,
+            watches: BTreeMap::new(),
+            limit: DEFAULT_LIMIT,
+        })
+    }
+
+    pub fn simulator(&self) -> &Simulator<R, W> {
+        &self.sim
+    }
+
+    /// Instructions `continue`, `next` and `finish` may execute before pausing
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    /// Where execution currently is, shown when the debugger starts
+    pub fn status(&self) -> String {
+        self.describe(&Pause::Stepped)
+    }
+
+    /// Run one command line and return its output
+    pub fn execute(&mut self, line: &str) -> Result<String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+
+        match (command, args) {
+            ("help" | "h", []) => Ok(HELP.to_owned()),
+            ("break" | "b", [location]) => {
+                let addr = self.location(location)?;
+                self.sim.add_breakpoint(addr);
+                Ok(format!("Breakpoint at {}", self.place(addr)))
+            }
+            ("delete" | "d", [location]) => {
+                let addr = self.address(location)?;
+                if self.sim.remove_breakpoint(addr) {
+                    Ok(format!("Deleted breakpoint at {}", self.name(addr)))
+                } else {
+                    Err(command_error(format!(
+                        "no breakpoint at {}",
+                        self.name(addr)
+                    )))
+                }
+            }
+            ("watch" | "w", [location]) => {
+                let addr = self.address(location)?;
+                self.watches.insert(addr, self.sim.peek(addr));
+                Ok(format!("Watching {}", self.name(addr)))
+            }
+            ("continue" | "c", []) => self.resume(|_| false),
+            ("step" | "s", []) => self.resume(|_| true),
+            ("next" | "n", []) => match Instruction::decode_lossy(self.sim.peek(self.sim.pc())) {
+                Instruction::Jsr { .. } | Instruction::Jsrr { .. } => self.run_to_return(0),
+                _ => self.resume(|_| true),
+            },
+            ("finish" | "f", []) => self.run_to_return(1),
+            ("regs" | "r", []) => Ok(self.registers()),
+            ("mem" | "m", [addr]) => self.memory(self.address(addr)?, 8),
+            ("mem" | "m", [addr, count]) => self.memory(self.address(addr)?, self.count(count)?),
+            ("disasm" | "x", []) => Ok(self.disassemble(self.sim.pc(), 8)),
+            ("disasm" | "x", [addr]) => Ok(self.disassemble(self.address(addr)?, 8)),
+            ("disasm" | "x", [addr, count]) => {
+                Ok(self.disassemble(self.address(addr)?, self.count(count)?))
+            }
+            _ => Err(command_error(format!(
+                "unknown command `{line}`, try `help`"
+            ))),
+        }
+    }
+
+    /// Step until `done` holds for an executed instruction, or something else stops
+    fn resume(&mut self, done: impl FnMut(Instruction) -> bool) -> Result<String> {
+        let pause = self.run_until(done)?;
+        Ok(self.describe(&pause))
+    }
+
+    fn run_until(&mut self, mut done: impl FnMut(Instruction) -> bool) -> Result<Pause> {
+        let start = self.sim.cycles();
+        let pause = loop {
+            if self.sim.halted() {
+                break Pause::Stopped(Stop::Halted);
+            }
+            if self.sim.cycles() - start >= self.limit {
+                break Pause::Stopped(Stop::Limit);
+            }
+
+            let instruction = Instruction::decode_lossy(self.sim.peek(self.sim.pc()));
+            let res = self.sim.step();
+            self.sim.output_mut().flush()?;
+            res?;
+
+            if let Some(pause) = self.check_watches() {
+                break pause;
+            }
+            if done(instruction) {
+                break Pause::Stepped;
+            }
+            let pc = self.sim.pc();
+            if !self.sim.halted() && self.sim.breakpoints().contains(&pc) {
+                break Pause::Stopped(Stop::Breakpoint(pc));
+            }
+        };
+
+        Ok(pause)
+    }
+
+    /// Run until a `RET` leaves `depth` levels of subroutine calls
+    fn run_to_return(&mut self, mut depth: usize) -> Result<String> {
+        let pause = self.run_until(|instruction| match instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => {
+                depth += 1;
+                false
+            }
+            Instruction::Jmp { base: Register::R7 } => {
+                depth = depth.saturating_sub(1);
+                depth == 0
+            }
+            _ => false,
+        })?;
+        let pause = match pause {
+            Pause::Stepped => Pause::Returned,
+            pause => pause,
+        };
+        Ok(self.describe(&pause))
+    }
+
+    fn check_watches(&mut self) -> Option<Pause> {
+        for (addr, old) in self.watches.iter_mut() {
+            let new = self.sim.peek(*addr);
+            if new != *old {
+                let pause = Pause::Watch {
+                    addr: *addr,
+                    old: *old,
+                    new,
+                };
+                *old = new;
+                return Some(pause);
+            }
+        }
+        None
+    }
+
+    fn describe(&self, pause: &Pause) -> String {
+        let pc = self.sim.pc();
+        let what = match pause {
+            Pause::Stopped(Stop::Halted) => {
+                return format!("Program halted after {} instructions", self.sim.cycles())
+            }
+            Pause::Stopped(Stop::Limit) => {
+                format!("Paused after {} instructions at", self.limit)
+            }
+            Pause::Stopped(Stop::Breakpoint(_)) => String::from("Breakpoint at"),
+            Pause::Returned => String::from("Returned to"),
+            Pause::Stepped => String::from("Stepped to"),
+            Pause::Watch { addr, old, new } => format!(
+                "{} changed from x{old:04X} to x{new:04X}, at",
+                self.name(*addr)
+            ),
+        };
+
+        match self.source_line(pc) {
+            Some(line) => format!("{what} {}\n    {line}", self.place(pc)),
+            None => format!("{what} {}", self.place(pc)),
+        }
+    }
+
+    /// Address and label, followed by the source location when there is one
+    fn place(&self, addr: u16) -> String {
+        match self.program.location_of(addr) {
+            Some((file, line)) => format!("{} ({}:{line})", self.name(addr), file.display()),
+            None => self.name(addr),
+        }
+    }
+
+    /// `x3001 LOOP`, or just the address without a label there
+    fn name(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(label) => format!("x{addr:04X} {label}"),
+            None => format!("x{addr:04X}"),
+        }
+    }
+
+    fn source_line(&self, addr: u16) -> Option<String> {
+        let (file, line) = self.program.location_of(addr)?;
+        let index = self.program.files().iter().position(|f| f == file)?;
+        let text = self.sources[index].get(line.checked_sub(1)?)?;
+        Some(text.trim().to_owned())
+    }
+
+    /// A label or constant
+    fn address(&self, s: &str) -> Result<u16> {
+        match self.program.symbol(s) {
+            Some(addr) => Ok(addr),
+            None => parse_constant(s)
+                .map_err(|_| command_error(format!("`{s}` is neither a label nor an address"))),
+        }
+    }
+
+    /// A label, constant or `FILE:LINE`, lines without code move to the next that has some
+    fn location(&self, s: &str) -> Result<u16> {
+        let Some((file, line)) = s
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)))
+        else {
+            return self.address(s);
+        };
+
+        self.program
+            .segments()
+            .iter()
+            .flat_map(|segment| (0..segment.words.len()).map(|i| segment.origin + i as u16))
+            .filter_map(|addr| {
+                let (path, at) = self.program.location_of(addr)?;
+                (at >= line && same_file(path, file)).then_some((at, addr))
+            })
+            .min()
+            .map(|(_, addr)| addr)
+            .ok_or_else(|| command_error(format!("no code at or after {s}")))
+    }
+
+    fn count(&self, s: &str) -> Result<usize> {
+        s.parse()
+            .or_else(|_| parse_constant(s).map(usize::from))
+            .map_err(|_| command_error(format!("`{s}` is not a count")))
+    }
+
+    fn registers(&self) -> String {
+        let mut lines: Vec<_> = (0..8)
+            .map(|r| {
+                let value = self.sim.register(Register::from_usize(r).unwrap());
+                format!("R{r}  x{value:04X}  {:>6}", value as i16)
+            })
+            .collect();
+        lines.push(format!(
+            "PC  {}  CC {}  cycles {}",
+            self.name(self.sim.pc()),
+            self.sim.condition(),
+            self.sim.cycles()
+        ));
+        lines.join("\n")
+    }
+
+    fn memory(&self, addr: u16, count: usize) -> Result<String> {
+        let words: Vec<_> = (0..count)
+            .map(|i| self.sim.peek(addr.wrapping_add(i as u16)))
+            .collect();
+        let lines: Vec<_> = words
+            .chunks(8)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let words: Vec<_> = chunk.iter().map(|w| format!("x{w:04X}")).collect();
+                format!(
+                    "x{:04X}  {}",
+                    addr.wrapping_add(row as u16 * 8),
+                    words.join(" ")
+                )
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn disassemble(&self, addr: u16, count: usize) -> String {
+        let mut lines = Vec::new();
+        for i in 0..count {
+            let addr = addr.wrapping_add(i as u16);
+            if let Some(label) = self.labels.get(&addr) {
+                lines.push(format!("{label}:"));
+            }
+
+            let marker = if addr == self.sim.pc() { "=>" } else { "  " };
+            let instruction = Instruction::decode(self.sim.peek(addr));
+            let mut line = format!("{marker} x{addr:04X}  {instruction}");
+            if let Some(label) = instruction
+                .target(addr)
+                .and_then(|target| self.labels.get(&target))
+            {
+                line += &format!("  ; {label}");
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+/// Whether `path` is the file written as `name`, or ends with it
+fn same_file(path: &Path, name: &str) -> bool {
+    path.ends_with(name) || path.file_name().is_some_and(|f| f == name)
+}
+
+fn command_error(message: String) -> Error {
+    Error::with_message(ErrorKind::SyntaxError, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{utils::OutputPaths, Assembler, AssemblerOptions};
+
+    fn debugger(path: &str) -> Debugger<&'static [u8], Vec<u8>> {
+        let mut asm = Assembler::new(
+            path.into(),
+            OutputPaths::default(),
+            AssemblerOptions::default(),
+        );
+        asm.build().unwrap();
+        Debugger::new(Program::from_assembler(&asm), &b""[..], Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_debugger_stepping() {
+        let mut db = debugger("asm/countdown.ggnm");
+        assert_eq!(
+            db.status(),
+            "Stepped to x3000 (asm/countdown.ggnm:3)\n    LD R1, COUNT"
+        );
+
+        assert_eq!(
+            db.execute("break PRINT").unwrap(),
+            "Breakpoint at x3006 PRINT (asm/countdown.ggnm:11)"
+        );
+        assert_eq!(
+            db.execute("continue").unwrap(),
+            "Breakpoint at x3006 PRINT (asm/countdown.ggnm:11)\n    PRINT   ST R7, SAVE_R7"
+        );
+        assert_eq!(
+            db.execute("finish").unwrap(),
+            "Returned to x3002 (asm/countdown.ggnm:5)\n    ADD R1, R1, #-1"
+        );
+        assert_eq!(db.simulator().output(), b"3\n");
+
+        db.execute("delete PRINT").unwrap();
+        db.execute("step").unwrap();
+        db.execute("step").unwrap();
+        assert_eq!(
+            db.execute("next").unwrap(),
+            "Returned to x3002 (asm/countdown.ggnm:5)\n    ADD R1, R1, #-1"
+        );
+        assert_eq!(db.simulator().output(), b"3\n2\n");
+
+        assert_eq!(
+            db.execute("break countdown.ggnm:7").unwrap(),
+            "Breakpoint at x3004 (asm/countdown.ggnm:7)"
+        );
+        db.execute("watch COUNT").unwrap();
+        assert_eq!(
+            db.execute("c").unwrap(),
+            "Breakpoint at x3004 (asm/countdown.ggnm:7)\n    ST R1, COUNT"
+        );
+        assert_eq!(
+            db.execute("c").unwrap(),
+            "x300E COUNT changed from x0003 to x0000, at x3005 (asm/countdown.ggnm:8)\n    HALT"
+        );
+        assert_eq!(
+            db.execute("c").unwrap(),
+            "Program halted after 36 instructions"
+        );
+    }
+
+    #[test]
+    fn test_debugger_inspect() {
+        let mut db = debugger("asm/include.ggnm");
+        db.execute("step").unwrap();
+
+        let regs = db.execute("regs").unwrap();
+        assert!(regs.starts_with("R0  x3003   12291\n"));
+        assert!(regs.ends_with("PC  x3001  CC Z  cycles 1"));
+        assert_eq!(
+            db.execute("mem HELLO_WORLD 10").unwrap(),
+            "x3003  x0048 x0065 x006C x006C x006F x002C x0020 x0057\nx300B  x006F x0072"
+        );
+        assert_eq!(
+            db.execute("disasm x3000 4").unwrap(),
+            "   x3000  LEA R0, #2  ; HELLO_WORLD\n=> x3001  PUTS\n   x3002  HALT\nHELLO_WORLD:\n   x3003  BR #72"
+        );
+
+        // Lines in an include report the included file
+        assert_eq!(
+            db.execute("break hello.inc:1").unwrap(),
+            "Breakpoint at x3003 HELLO_WORLD (asm/include/hello.inc:2)"
+        );
+        assert!(db.execute("break NOWHERE").is_err());
+        assert!(db.execute("frobnicate").is_err());
+    }
+}
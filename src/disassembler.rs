@@ -0,0 +1,499 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    enums::{Parseable, Token},
+    error::{Error, ErrorKind, Result},
+    extension::{CustomInstruction, Extensions, OperandKind},
+    formatter::format_source_with,
+    instruction::{sign_extend, Instruction},
+};
+
+/// Turn an image back into source that assembles to the same words
+///
+/// `image` is in `.obj` layout, origin first. Labels from `symbols` are used
+/// where they are valid in source, PC-relative targets without one get a
+/// generated `Lxxxx` label. Words reachable from the origin are treated as
+/// code, everything else as data written with `.STRINGZ`, `.BLKW` or
+/// `.FILL`. The output uses the native dialect, where a plain `BR` never
+/// branches.
+pub fn disassemble(image: &[u16], symbols: &HashMap<String, u16>) -> Result<String> {
+    disassemble_with(image, symbols, &Extensions::default())
+}
+
+/// Like [`disassemble`], writing words of the reserved opcode that
+/// `extensions` decode as their custom instructions
+///
+/// Fails if a mnemonic of `extensions` collides with a built-in one.
+pub fn disassemble_with(
+    image: &[u16],
+    symbols: &HashMap<String, u16>,
+    extensions: &Extensions,
+) -> Result<String> {
+    let Some((&origin, words)) = image.split_first() else {
+        return Err(Error::with_message(
+            ErrorKind::ValueError,
+            String::from("image has no origin"),
+        ));
+    };
+
+    extensions.check()?;
+
+    let mut disassembly = Disassembly::new(origin, words, symbols, extensions);
+    disassembly.trace();
+    disassembly.label_targets();
+    let source = disassembly.emit();
+
+    format_source_with("<disassembly>", &source, extensions)
+        .map_err(|e| Error::with_message(ErrorKind::SyntaxError, e.to_string()))
+}
+
+struct Disassembly<'a> {
+    origin: u16,
+    words: &'a [u16],
+    /// Labels by address, the image end may carry labels too
+    labels: BTreeMap<u16, Vec<String>>,
+    /// Addresses named by the given symbols, used for `.FILL` operands
+    symbols: HashMap<u16, String>,
+    extensions: &'a Extensions,
+    code: Vec<bool>,
+}
+
+impl<'a> Disassembly<'a> {
+    fn new(
+        origin: u16,
+        words: &'a [u16],
+        symbols: &HashMap<String, u16>,
+        extensions: &'a Extensions,
+    ) -> Self {
+        let mut disassembly = Self {
+            origin,
+            words,
+            labels: BTreeMap::new(),
+            symbols: HashMap::new(),
+            extensions,
+            code: vec![false; words.len()],
+        };
+
+        let mut named: Vec<_> = symbols
+            .iter()
+            .filter(|(name, _)| is_valid_label(name))
+            .filter(|(_, addr)| disassembly.index(**addr).is_some() || disassembly.is_end(**addr))
+            .collect();
+        named.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+        for (name, addr) in named {
+            disassembly.symbols.entry(*addr).or_insert(name.clone());
+            disassembly
+                .labels
+                .entry(*addr)
+                .or_default()
+                .push(name.clone());
+        }
+
+        disassembly
+    }
+
+    fn addr(&self, index: usize) -> u16 {
+        self.origin.wrapping_add(index as u16)
+    }
+
+    /// Index of `addr` in the image
+    fn index(&self, addr: u16) -> Option<usize> {
+        let index = addr.wrapping_sub(self.origin) as usize;
+        (index < self.words.len()).then_some(index)
+    }
+
+    /// The address just past the image, which a label may still name
+    fn is_end(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.origin) as usize == self.words.len()
+    }
+
+    fn can_label(&self, addr: u16) -> bool {
+        self.index(addr).is_some() || self.is_end(addr)
+    }
+
+    /// Mark the words reachable from the origin as code
+    fn trace(&mut self) {
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            if index >= self.words.len() || self.code[index] {
+                continue;
+            }
+
+            let addr = self.addr(index);
+            if let Some((custom, fields)) = self.extensions.decode(self.words[index]) {
+                // Custom instructions are assumed to fall through
+                if custom
+                    .targets(&fields, addr)
+                    .iter()
+                    .all(|t| self.can_label(*t))
+                {
+                    self.code[index] = true;
+                    pending.push(index + 1);
+                }
+                continue;
+            }
+
+            let instruction = Instruction::decode(self.words[index]);
+            if !self.is_expressible(&instruction, addr) {
+                continue;
+            }
+            self.code[index] = true;
+
+            let target = instruction.target(addr).and_then(|t| self.index(t));
+            let falls_through = match instruction {
+                Instruction::Br { n, z, p, .. } => {
+                    if n || z || p {
+                        pending.extend(target);
+                    }
+                    !(n && z && p)
+                }
+                Instruction::Jsr { .. } => {
+                    pending.extend(target);
+                    true
+                }
+                Instruction::Jmp { .. } | Instruction::Rti => false,
+                Instruction::Trap { vector } => vector != 0x25,
+                _ => true,
+            };
+            if falls_through {
+                pending.push(index + 1);
+            }
+        }
+    }
+
+    /// Whether the assembler can write `instruction` at `addr`
+    ///
+    /// Only BR takes a constant offset, the other PC-relative instructions
+    /// need a label and therefore a target inside the image.
+    fn is_expressible(&self, instruction: &Instruction, addr: u16) -> bool {
+        match instruction {
+            Instruction::Data(_) => false,
+            Instruction::Trap { vector } => (0x20..=0x25).contains(vector),
+            Instruction::Br { .. } => true,
+            _ => match instruction.target(addr) {
+                Some(target) => self.can_label(target),
+                None => true,
+            },
+        }
+    }
+
+    /// Give every in-image target of a code word a label
+    fn label_targets(&mut self) {
+        for index in 0..self.words.len() {
+            if !self.code[index] {
+                continue;
+            }
+
+            let addr = self.addr(index);
+            let targets = match self.extensions.decode(self.words[index]) {
+                Some((custom, fields)) => custom.targets(&fields, addr),
+                None => Instruction::decode(self.words[index])
+                    .target(addr)
+                    .into_iter()
+                    .collect(),
+            };
+            for target in targets {
+                if self.can_label(target) && !self.labels.contains_key(&target) {
+                    self.labels.insert(target, vec![format!("L{target:04X}")]);
+                }
+            }
+        }
+    }
+
+    /// Source text before alignment
+    fn emit(&self) -> String {
+        let mut lines = vec![
+            String::from("; Disassembled image, assembles with the native dialect"),
+            format!(".ORIG x{:04X}", self.origin),
+        ];
+
+        let mut index = 0;
+        while index < self.words.len() {
+            let addr = self.addr(index);
+            let label = self.take_labels(addr, &mut lines);
+            let (text, len) = if self.code[index] {
+                (self.instruction(index), 1)
+            } else {
+                self.data(index)
+            };
+
+            lines.push(match label {
+                Some(label) => format!("{label} {text}"),
+                None => text,
+            });
+            index += len;
+        }
+
+        let end = self.addr(self.words.len());
+        if let Some(label) = self.take_labels(end, &mut lines) {
+            lines.push(label);
+        }
+        lines.push(String::from(".END"));
+
+        lines.join("\n")
+    }
+
+    /// Write all but the last label at `addr` on their own lines, return the last
+    fn take_labels(&self, addr: u16, lines: &mut Vec<String>) -> Option<String> {
+        let labels = self.labels.get(&addr)?;
+        let (last, rest) = labels.split_last()?;
+        lines.extend(rest.iter().cloned());
+        Some(last.clone())
+    }
+
+    fn label_of(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .get(&addr)
+            .and_then(|labels| labels.last())
+            .map(String::as_str)
+    }
+
+    fn instruction(&self, index: usize) -> String {
+        let addr = self.addr(index);
+        if let Some((custom, fields)) = self.extensions.decode(self.words[index]) {
+            return self.custom(custom, &fields, addr);
+        }
+        let instruction = Instruction::decode(self.words[index]);
+        let mnemonic = instruction.mnemonic();
+        let Some(label) = instruction.target(addr).and_then(|t| self.label_of(t)) else {
+            return instruction.to_string();
+        };
+
+        match instruction {
+            Instruction::Br { .. } | Instruction::Jsr { .. } => format!("{mnemonic} {label}"),
+            Instruction::Ld { dr: r, .. }
+            | Instruction::Ldi { dr: r, .. }
+            | Instruction::Lea { dr: r, .. }
+            | Instruction::St { sr: r, .. }
+            | Instruction::Sti { sr: r, .. } => format!("{mnemonic} {r:?}, {label}"),
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// Source of a custom instruction, its PC offsets written as labels
+    fn custom(&self, custom: &CustomInstruction, fields: &[u16], addr: u16) -> String {
+        let mut targets = custom.targets(fields, addr).into_iter();
+        let operands: Vec<_> = custom
+            .operands()
+            .iter()
+            .zip(fields)
+            .map(|(kind, field)| match kind {
+                OperandKind::Reg => format!("R{field}"),
+                OperandKind::Imm(bits) => format!("#{}", sign_extend(*field, *bits)),
+                OperandKind::Unsigned(_) => format!("#{field}"),
+                // Traced code only has targets that were given labels
+                OperandKind::PcOffset9 | OperandKind::PcOffset11 => targets
+                    .next()
+                    .and_then(|target| self.label_of(target))
+                    .unwrap_or_default()
+                    .to_owned(),
+            })
+            .collect();
+        format!("{} {}", custom.mnemonic(), operands.join(", "))
+            .trim_end()
+            .to_owned()
+    }
+
+    /// A data directive starting at `index` and the number of words it covers
+    fn data(&self, index: usize) -> (String, usize) {
+        // Runs stop at code and at labels, which must start a line
+        let run = self.words[index..]
+            .iter()
+            .enumerate()
+            .take_while(|(i, _)| {
+                *i == 0
+                    || (!self.code[index + i] && !self.labels.contains_key(&self.addr(index + i)))
+            })
+            .count();
+        let run = &self.words[index..index + run];
+
+        let chars: String = run
+            .iter()
+            .map_while(|word| string_char(*word))
+            .collect::<Vec<_>>()
+            .concat();
+        let len = run
+            .iter()
+            .take_while(|word| string_char(**word).is_some())
+            .count();
+        if len > 0 && run.get(len) == Some(&0) {
+            return (format!(".STRINGZ \"{chars}\""), len + 1);
+        }
+
+        let zeros = run.iter().take_while(|word| **word == 0).count();
+        if zeros > 1 {
+            return (format!(".BLKW #{zeros}"), zeros);
+        }
+
+        let word = self.words[index];
+        let text = match self.symbols.get(&word) {
+            Some(name) => format!(".FILL {name}"),
+            None => format!(".FILL x{word:04X}"),
+        };
+        // Keep what an instruction the assembler cannot write would have been
+        let instruction = Instruction::decode(word);
+        match instruction {
+            Instruction::Data(_) => (text, 1),
+            _ => (format!("{text} ; {instruction}"), 1),
+        }
+    }
+}
+
+/// Source spelling of a word inside `.STRINGZ`
+fn string_char(word: u16) -> Option<&'static str> {
+    const PRINTABLE: &str = " !#$%&'()*+,-./0123456789:<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+    match word {
+        0x09 => Some("\\t"),
+        0x0a => Some("\\n"),
+        0x1b => Some("\\e"),
+        _ => {
+            let c = u8::try_from(word).ok()? as char;
+            let i = PRINTABLE.find(c)?;
+            Some(&PRINTABLE[i..i + 1])
+        }
+    }
+}
+
+/// Whether `name` lexes as a label
+fn is_valid_label(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '"'))
+        && matches!(Token::parse(name), Ok(Token::Label(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{assemble, formats::read_obj, symbols::read_sym, AssemblerOptions};
+
+    fn round_trip(rom: &str) -> String {
+        let image = read_obj(File::open(format!("roms/{rom}.obj")).unwrap()).unwrap();
+        let sym_path = format!("roms/{rom}.sym");
+        let symbols = if Path::new(&sym_path).exists() {
+            read_sym(File::open(sym_path).unwrap()).unwrap()
+        } else {
+            HashMap::new()
+        };
+
+        let source = disassemble(&image, &symbols).unwrap();
+        let program = assemble(&source, &AssemblerOptions::default()).unwrap();
+        assert_eq!(program.image(), image, "{rom} does not round-trip");
+
+        let program = assemble(
+            &disassemble(&image, &HashMap::new()).unwrap(),
+            &AssemblerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(program.image(), image, "{rom} without symbols");
+
+        source
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let source = round_trip("test");
+        assert_eq!(
+            source,
+            "\
+; Disassembled image, assembles with the native dialect
+.ORIG x3000
+            LEA   R0, HELLO_WORLD
+            PUTS
+            HALT
+HELLO_WORLD .STRINGZ \"Hello, World\"
+.END
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_roms() {
+        for entry in std::fs::read_dir("roms").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "obj") {
+                round_trip(path.file_stem().unwrap().to_str().unwrap());
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_disassemble_any_words(
+            origin in 0u16..0xf000,
+            words in prop::collection::vec(any::<u16>(), 1..64),
+        ) {
+            let mut image = vec![origin];
+            image.extend(words);
+
+            let source = disassemble(&image, &HashMap::new()).unwrap();
+            let program = assemble(&source, &AssemblerOptions::default()).unwrap();
+            prop_assert_eq!(program.image(), image);
+        }
+    }
+
+    #[test]
+    fn test_disassemble_data() {
+        let image = [
+            0x3000, 0x0e02, 0x0000, 0x0000, 0xf025, 0x2200, 0xd123, 0x0009, 0x0041, 0x0000,
+        ];
+        let symbols = HashMap::from([
+            ("xbad".to_owned(), 0x3003),
+            ("DATA".to_owned(), 0x3003),
+            ("MORE".to_owned(), 0x3003),
+        ]);
+        let source = disassemble(&image, &symbols).unwrap();
+        assert_eq!(
+            source,
+            "\
+; Disassembled image, assembles with the native dialect
+.ORIG x3000
+     BRnzp MORE
+     .BLKW #2
+DATA
+MORE HALT
+     .FILL x2200  ; LD R1, #0
+     .FILL xD123
+     .STRINGZ \"\\tA\"
+.END
+"
+        );
+        let program = assemble(&source, &AssemblerOptions::default()).unwrap();
+        assert_eq!(program.image(), image);
+    }
+
+    #[test]
+    fn test_disassemble_extension() {
+        use crate::extension::{CustomInstruction, Extension, OperandKind::*};
+
+        let extension = Extension::new()
+            .instruction(
+                CustomInstruction::new("MUL", &[Reg, Reg], |f| f[0] << 6 | f[1] << 3)
+                    .decoder(|bits| (bits & 0xe07 == 0).then(|| vec![bits >> 6, bits >> 3 & 7])),
+            )
+            .instruction(
+                CustomInstruction::new("JNZ", &[PcOffset11], |f| 0x800 | f[0])
+                    .decoder(|bits| (bits & 0x800 != 0).then(|| vec![bits & 0x7ff])),
+            );
+        let options = AssemblerOptions::builder().extension(extension).build();
+        let image = [0x3000, 0xd050, 0xdffe, 0xf025, 0xd001];
+        let source = disassemble_with(&image, &HashMap::new(), &options.extensions).unwrap();
+        assert_eq!(
+            source,
+            "\
+; Disassembled image, assembles with the native dialect
+.ORIG x3000
+L3000 MUL   R1, R2
+      JNZ   L3000
+      HALT
+      .FILL xD001
+.END
+"
+        );
+        let program = assemble(&source, &options).unwrap();
+        assert_eq!(program.image(), image);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::enums::{Register, Token};
-use num_traits::ToPrimitive;
+use crate::enums::{OpCode, Register};
+use crate::instruction::{sign_extend, Instruction, Lc3bInstruction, Operand, Shift};
+use num_traits::{FromPrimitive, ToPrimitive};
 
 pub fn encode_fill(v: u16) -> Vec<u16> {
     vec![v]
@@ -19,123 +20,257 @@ pub fn encode_orig(origin: u16) -> Vec<u16> {
     vec![origin]
 }
 
-pub fn encode_br(t: &Token, offset: u16) -> Vec<u16> {
-    let mut com = 0u16;
-    if let Token::Op(op) = t {
-        let fin_op = op.to_u16().unwrap();
-        com |= fin_op << 9;
+pub fn encode_br(op: OpCode, offset: u16) -> Vec<u16> {
+    // BR opcodes are numbered by their nzp bits
+    let cond = op.to_u16().unwrap();
+    encode(Instruction::Br {
+        n: cond & 0b100 != 0,
+        z: cond & 0b010 != 0,
+        p: cond & 0b001 != 0,
+        offset: sign_extend(offset, 9),
+    })
+}
+
+/// Long-range replacement for an out-of-range BR
+///
+/// Branches around an absolute jump through R7 using the inverted condition:
+/// `BR(!nzp) #3; LD R7, #1; JMP R7; .FILL addr`.
+/// The inverted branch is omitted for BRnzp.
+pub fn encode_far_br(op: OpCode, addr: u16) -> Vec<u16> {
+    let mut bin = Vec::new();
+    let cond = op.to_u16().unwrap();
+    if cond != 0b111 {
+        let inverted = OpCode::from_u16(!cond & 0b111).unwrap();
+        bin.append(&mut encode_br(inverted, 3));
     }
-    com |= offset;
-    vec![com]
+    bin.append(&mut encode_ld(Register::R7, 1));
+    bin.append(&mut encode_jmp(Register::R7));
+    bin.append(&mut encode_fill(addr));
+    bin
+}
+
+/// Long-range replacement for an out-of-range JSR
+///
+/// `LD R7, #2; JSRR R7; BRnzp #1; .FILL addr`
+pub fn encode_far_jsr(addr: u16) -> Vec<u16> {
+    let mut bin = Vec::new();
+    bin.append(&mut encode_ld(Register::R7, 2));
+    bin.append(&mut encode_jsrr(Register::R7));
+    bin.append(&mut encode_br(OpCode::Brnzp, 1));
+    bin.append(&mut encode_fill(addr));
+    bin
 }
 
 pub fn encode_add_reg(dr: Register, sr1: Register, sr2: Register) -> Vec<u16> {
-    let com =
-        (dr.to_u16().unwrap() << 9) | (sr1.to_u16().unwrap() << 6) | 0x1000 | sr2.to_u16().unwrap();
-    vec![com]
+    encode(Instruction::Add {
+        dr,
+        sr1,
+        src: Operand::Reg(sr2),
+    })
 }
 
 pub fn encode_add_imm(dr: Register, sr1: Register, imm: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | (sr1.to_u16().unwrap() << 6) | 0x1000 | imm | (1 << 5);
-    vec![com]
+    encode(Instruction::Add {
+        dr,
+        sr1,
+        src: Operand::Imm(sign_extend(imm, 5)),
+    })
 }
 
 pub fn encode_and_reg(dr: Register, sr1: Register, sr2: Register) -> Vec<u16> {
-    let com =
-        (dr.to_u16().unwrap() << 9) | (sr1.to_u16().unwrap() << 6) | 0x5000 | sr2.to_u16().unwrap();
-    vec![com]
+    encode(Instruction::And {
+        dr,
+        sr1,
+        src: Operand::Reg(sr2),
+    })
 }
 
 pub fn encode_and_imm(dr: Register, sr1: Register, imm: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | (sr1.to_u16().unwrap() << 6) | 0x5000 | imm | (1 << 5);
-    vec![com]
+    encode(Instruction::And {
+        dr,
+        sr1,
+        src: Operand::Imm(sign_extend(imm, 5)),
+    })
 }
 
 pub fn encode_jmp(sr1: Register) -> Vec<u16> {
-    let com = (sr1.to_u16().unwrap() << 6) | 0xc000;
-    vec![com]
+    encode(Instruction::Jmp { base: sr1 })
 }
 
 pub fn encode_jsr(offset: u16) -> Vec<u16> {
-    let com = 0x4800 | offset;
-    vec![com]
+    encode(Instruction::Jsr {
+        offset: sign_extend(offset, 11),
+    })
 }
 
 pub fn encode_jsrr(sr1: Register) -> Vec<u16> {
-    let com = 0x4000 | (sr1.to_u16().unwrap() << 6);
-    vec![com]
+    encode(Instruction::Jsrr { base: sr1 })
 }
 
 pub fn encode_ld(dr: Register, offset: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | offset | 0x2000;
-    vec![com]
+    encode(Instruction::Ld {
+        dr,
+        offset: sign_extend(offset, 9),
+    })
 }
 
 pub fn encode_ldi(dr: Register, offset: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | offset | 0xa000;
-    vec![com]
+    encode(Instruction::Ldi {
+        dr,
+        offset: sign_extend(offset, 9),
+    })
 }
 
 pub fn encode_ldr(dr: Register, sr: Register, offset: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | (sr.to_u16().unwrap() << 6) | offset | 0x6000;
-    vec![com]
+    encode(Instruction::Ldr {
+        dr,
+        base: sr,
+        offset: sign_extend(offset, 6),
+    })
 }
 
 pub fn encode_lea(dr: Register, offset: u16) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | offset | 0xe000;
-    vec![com]
+    encode(Instruction::Lea {
+        dr,
+        offset: sign_extend(offset, 9),
+    })
 }
 
 pub fn encode_not(dr: Register, sr: Register) -> Vec<u16> {
-    let com = (dr.to_u16().unwrap() << 9) | (sr.to_u16().unwrap() << 6) | 0x903f;
-    vec![com]
+    encode(Instruction::Not { dr, sr })
 }
 
 pub fn encode_ret() -> Vec<u16> {
-    vec![0b1100000111000000]
+    encode_jmp(Register::R7)
 }
 
 pub fn encode_rti() -> Vec<u16> {
-    vec![0b1000000000000000]
+    encode(Instruction::Rti)
 }
 
 pub fn encode_st(sr: Register, offset: u16) -> Vec<u16> {
-    let com = (sr.to_u16().unwrap() << 9) | offset | 0x3000;
-    vec![com]
+    encode(Instruction::St {
+        sr,
+        offset: sign_extend(offset, 9),
+    })
 }
 
 pub fn encode_sti(sr: Register, offset: u16) -> Vec<u16> {
-    let com = (sr.to_u16().unwrap() << 9) | offset | 0xb000;
-    vec![com]
+    encode(Instruction::Sti {
+        sr,
+        offset: sign_extend(offset, 9),
+    })
 }
 
 pub fn encode_str(sr: Register, baser: Register, offset: u16) -> Vec<u16> {
-    let com = (sr.to_u16().unwrap() << 9) | (baser.to_u16().unwrap() << 6) | offset | 0x7000;
-    vec![com]
+    encode(Instruction::Str {
+        sr,
+        base: baser,
+        offset: sign_extend(offset, 6),
+    })
+}
+
+pub fn encode_trap(vector: u8) -> Vec<u16> {
+    encode(Instruction::Trap { vector })
 }
 
 pub fn encode_halt() -> Vec<u16> {
-    vec![0xf025]
+    encode_trap(0x25)
 }
 
 pub fn encode_in() -> Vec<u16> {
-    vec![0xf023]
+    encode_trap(0x23)
 }
 
 pub fn encode_out() -> Vec<u16> {
-    vec![0xf021]
+    encode_trap(0x21)
 }
 
 pub fn encode_getc() -> Vec<u16> {
-    vec![0xf020]
+    encode_trap(0x20)
 }
 
 pub fn encode_puts() -> Vec<u16> {
-    vec![0xf022]
+    encode_trap(0x22)
 }
 
 pub fn encode_putsp() -> Vec<u16> {
-    vec![0xf024]
+    encode_trap(0x24)
+}
+
+/// LC-3b `LDB`, the offset counts bytes
+pub fn encode_ldb(dr: Register, base: Register, offset: u16) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Ldb {
+        dr,
+        base,
+        offset: sign_extend(offset, 6),
+    })
+}
+
+/// LC-3b `STB`, the offset counts bytes
+pub fn encode_stb(sr: Register, base: Register, offset: u16) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Stb {
+        sr,
+        base,
+        offset: sign_extend(offset, 6),
+    })
+}
+
+/// LC-3b `LDW`, the offset counts words
+pub fn encode_ldw(dr: Register, base: Register, offset: u16) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Ldw {
+        dr,
+        base,
+        offset: sign_extend(offset, 6),
+    })
+}
+
+/// LC-3b `STW`, the offset counts words
+pub fn encode_stw(sr: Register, base: Register, offset: u16) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Stw {
+        sr,
+        base,
+        offset: sign_extend(offset, 6),
+    })
+}
+
+pub fn encode_xor_reg(dr: Register, sr1: Register, sr2: Register) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Xor {
+        dr,
+        sr1,
+        src: Operand::Reg(sr2),
+    })
+}
+
+pub fn encode_xor_imm(dr: Register, sr1: Register, imm: u16) -> Vec<u16> {
+    encode_lc3b(Lc3bInstruction::Xor {
+        dr,
+        sr1,
+        src: Operand::Imm(sign_extend(imm, 5)),
+    })
+}
+
+/// LC-3b `LSHF`, `RSHFL` or `RSHFA` by a 4-bit amount
+pub fn encode_shf(op: OpCode, dr: Register, sr: Register, amount: u16) -> Vec<u16> {
+    let shift = match op {
+        OpCode::Rshfl => Shift::Rshfl,
+        OpCode::Rshfa => Shift::Rshfa,
+        _ => Shift::Lshf,
+    };
+    encode_lc3b(Lc3bInstruction::Shf {
+        shift,
+        dr,
+        sr,
+        amount,
+    })
+}
+
+fn encode(instruction: Instruction) -> Vec<u16> {
+    vec![instruction.encode()]
+}
+
+fn encode_lc3b(instruction: Lc3bInstruction) -> Vec<u16> {
+    vec![instruction.encode()]
 }
 
 #[cfg(test)]
@@ -172,6 +307,23 @@ mod tests {
         assert_eq!(encode_orig(0xDEAD), vec![0xDEAD]);
     }
 
+    #[test]
+    fn test_far_br() {
+        assert_eq!(
+            encode_far_br(OpCode::Brz, 0x4000),
+            vec![0x0a03, 0x2e01, 0xc1c0, 0x4000]
+        );
+        assert_eq!(
+            encode_far_br(OpCode::Brnzp, 0x4000),
+            vec![0x2e01, 0xc1c0, 0x4000]
+        );
+    }
+
+    #[test]
+    fn test_far_jsr() {
+        assert_eq!(encode_far_jsr(0x4000), vec![0x2e02, 0x41c0, 0x0e01, 0x4000]);
+    }
+
     #[test]
     fn test_add() {
         assert_eq!(
@@ -331,4 +483,36 @@ mod tests {
     fn test_putsp() {
         assert_eq!(encode_putsp(), vec![0xf024]);
     }
+
+    #[test]
+    fn test_lc3b() {
+        assert_eq!(encode_ldb(Register::R1, Register::R2, 0x3f), vec![0x22bf]);
+        assert_eq!(encode_stb(Register::R1, Register::R2, 1), vec![0x3281]);
+        assert_eq!(encode_ldw(Register::R3, Register::R6, 2), vec![0x6782]);
+        assert_eq!(encode_stw(Register::R3, Register::R6, 0x3e), vec![0x77be]);
+        assert_eq!(
+            encode_xor_reg(Register::R0, Register::R1, Register::R2),
+            vec![0x9042]
+        );
+        assert_eq!(
+            encode_xor_imm(Register::R0, Register::R1, 0x1f),
+            vec![0x907f]
+        );
+    }
+
+    #[test]
+    fn test_shf() {
+        assert_eq!(
+            encode_shf(OpCode::Lshf, Register::R1, Register::R1, 3),
+            vec![0xd243]
+        );
+        assert_eq!(
+            encode_shf(OpCode::Rshfl, Register::R1, Register::R1, 3),
+            vec![0xd253]
+        );
+        assert_eq!(
+            encode_shf(OpCode::Rshfa, Register::R1, Register::R1, 15),
+            vec![0xd27f]
+        );
+    }
 }
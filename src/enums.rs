@@ -21,15 +21,15 @@ pub enum Register {
 }
 
 #[repr(u16)]
-#[derive(ToPrimitive, Clone, Copy, Debug, PartialEq)]
+#[derive(FromPrimitive, ToPrimitive, Clone, Copy, Debug, PartialEq)]
 pub enum OpCode {
-    Br = 0b0000000, // 0b0000 branch
-    Brp,            // 0b0000 branch if n
+    Br = 0b0000000, // 0b0000 never branches, nzp = 000
+    Brp,            // 0b0000 branch if p
     Brz,            // 0b0000 branch if z
-    Brzp,           // 0b0000 branch if p
-    Brn,            // 0b0000 branch if zp
+    Brzp,           // 0b0000 branch if zp
+    Brn,            // 0b0000 branch if n
     Brnp,           // 0b0000 branch if np
-    Brnz,           // 0b0000 barnch if nz
+    Brnz,           // 0b0000 branch if nz
     Brnzp,          // 0b0000 branch if nzp
     Add,            // 0b0001 add
     Ld,             // 0b0010 load
@@ -56,6 +56,16 @@ pub enum OpCode {
     In,          // 0x23
     PutsP,       // 0x24
     Halt,        // 0x25
+
+    /* LC-3b */
+    Ldb = 0x40, // 0b0010 load byte
+    Stb,        // 0b0011 store byte
+    Ldw,        // 0b0110 load word
+    Stw,        // 0b0111 store word
+    Xor,        // 0b1001 exclusive or
+    Lshf,       // 0b1101 shift left
+    Rshfl,      // 0b1101 shift right logical
+    Rshfa,      // 0b1101 shift right arithmetic
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -65,12 +75,21 @@ pub enum Directive {
     Fill,
     Blkw,
     Stringz,
+    Include,
+    /// Import the labels of a symbol table as absolute addresses
+    ImportSym,
+    /// Export a label from a relocatable object
+    Global,
+    /// Declare a label defined by another object
+    Extern,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Label(String),
     Op(OpCode),
+    /// Upper case mnemonic of an instruction from a registered extension
+    Custom(String),
     Dir(Directive),
     Const(u16),
     Reg(Register),
@@ -118,6 +137,14 @@ impl Parseable for OpCode {
             "IN" => Ok(Self::In),
             "PUTSP" => Ok(Self::PutsP),
             "HALT" => Ok(Self::Halt),
+            "LDB" => Ok(Self::Ldb),
+            "STB" => Ok(Self::Stb),
+            "LDW" => Ok(Self::Ldw),
+            "STW" => Ok(Self::Stw),
+            "XOR" => Ok(Self::Xor),
+            "LSHF" => Ok(Self::Lshf),
+            "RSHFL" => Ok(Self::Rshfl),
+            "RSHFA" => Ok(Self::Rshfa),
 
             _ => Err(Error::new(ErrorKind::ParseOpCodeError)),
         }
@@ -149,6 +176,10 @@ impl Parseable for Directive {
             ".FILL" => Ok(Self::Fill),
             ".BLKW" => Ok(Self::Blkw),
             ".STRINGZ" => Ok(Self::Stringz),
+            ".INCLUDE" => Ok(Self::Include),
+            ".IMPORTSYM" => Ok(Self::ImportSym),
+            ".GLOBAL" => Ok(Self::Global),
+            ".EXTERN" => Ok(Self::Extern),
 
             _ => Err(Error::new(ErrorKind::ParseDirectiveError)),
         }
@@ -165,7 +196,9 @@ impl Parseable for Token {
             "BR" | "BRN" | "BRZ" | "BRP" | "BRZP" | "BRNP" | "BRNZ" | "BRNZP" | "ADD" | "LD"
             | "ST" | "JSR" | "JSRR" | "AND" | "LDR" | "STR" | "RTI" | "NOT" | "LDI" | "STI"
             | "RET" | "JMP" | "RES" | "LEA" | "TRAP" | "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP"
-            | "HALT" => Self::Op(OpCode::parse(&upper)?),
+            | "HALT" | "LDB" | "STB" | "LDW" | "STW" | "XOR" | "LSHF" | "RSHFL" | "RSHFA" => {
+                Self::Op(OpCode::parse(&upper)?)
+            }
 
             // Register
             "R0" | "R1" | "R2" | "R3" | "R4" | "R5" | "R6" | "R7" => {
@@ -173,9 +206,8 @@ impl Parseable for Token {
             }
 
             // Directive
-            ".ORIG" | ".END" | ".FILL" | ".BLKW" | ".STRINGZ" => {
-                Self::Dir(Directive::parse(&upper)?)
-            }
+            ".ORIG" | ".END" | ".FILL" | ".BLKW" | ".STRINGZ" | ".INCLUDE" | ".IMPORTSYM"
+            | ".GLOBAL" | ".EXTERN" => Self::Dir(Directive::parse(&upper)?),
 
             _ => {
                 if s.starts_with(['x', '#', 'b']) {
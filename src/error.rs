@@ -12,6 +12,13 @@ pub enum ErrorKind {
     UnexpectedEof,
     SyntaxError,
     ValueError,
+    EncodingError,
+    LintError,
+    ManifestError,
+    ExecutionError,
+    TestSpecError,
+    ObjectError,
+    LinkError,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,6 +34,10 @@ impl Error {
             message: kind.as_str().to_owned(),
         }
     }
+
+    pub fn with_message(kind: ErrorKind, message: String) -> Self {
+        Self { kind, message }
+    }
 }
 
 impl ErrorKind {
@@ -42,6 +53,13 @@ impl ErrorKind {
             ErrorKind::SyntaxError => "invalid syntax",
             ErrorKind::MissingLabelError => "missing label",
             ErrorKind::ValueError => "invalid value",
+            ErrorKind::EncodingError => "invalid UTF-8 in source",
+            ErrorKind::LintError => "denied lint",
+            ErrorKind::ManifestError => "invalid project manifest",
+            ErrorKind::ExecutionError => "program fault",
+            ErrorKind::TestSpecError => "invalid test spec",
+            ErrorKind::ObjectError => "invalid relocatable object",
+            ErrorKind::LinkError => "link failed",
         }
     }
 }
@@ -52,6 +70,8 @@ impl fmt::Display for Error {
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Self {
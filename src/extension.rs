@@ -0,0 +1,357 @@
+use std::{fmt, sync::Arc};
+
+use crate::{
+    enums::{Parseable, Token},
+    error::{Error, ErrorKind, Result},
+    instruction::sign_extend,
+    object::RelocationKind,
+    target::Operands,
+};
+
+/// Opcode the LC-3 leaves reserved, shared by all custom instructions
+pub const RESERVED_OPCODE: u16 = 0b1101;
+
+/// Operand of a custom instruction and the field it is passed as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperandKind {
+    /// A register, passed as its number
+    Reg,
+    /// A signed constant of the given bits, passed masked to them
+    Imm(u16),
+    /// An unsigned constant below `2^bits`
+    Unsigned(u16),
+    /// A label within a 9-bit PC offset, passed masked to 9 bits
+    PcOffset9,
+    /// A label within an 11-bit PC offset, passed masked to 11 bits
+    PcOffset11,
+}
+
+impl OperandKind {
+    /// Bits of the field passed to the encoder
+    pub fn bits(&self) -> u16 {
+        match self {
+            Self::Reg => 3,
+            Self::Imm(bits) | Self::Unsigned(bits) => *bits,
+            Self::PcOffset9 => 9,
+            Self::PcOffset11 => 11,
+        }
+    }
+
+    /// Bits of the PC-relative offset, None for other operands
+    pub fn offset_bits(&self) -> Option<u16> {
+        match self {
+            Self::PcOffset9 => Some(9),
+            Self::PcOffset11 => Some(11),
+            _ => None,
+        }
+    }
+}
+
+type Encode = dyn Fn(&[u16]) -> u16 + Send + Sync;
+type Decode = dyn Fn(u16) -> Option<Vec<u16>> + Send + Sync;
+
+/// An instruction in the reserved `1101` opcode
+///
+/// The operands are read as given by the signature and their fields passed
+/// to the encoder, which returns the low 12 bits of the word. A PC offset of
+/// a relocatable object is patched in the low bits of the word, as for `LD`
+/// and `JSR`, so keep it there when the label may be external.
+#[derive(Clone)]
+pub struct CustomInstruction {
+    mnemonic: String,
+    operands: Vec<OperandKind>,
+    encode: Arc<Encode>,
+    decode: Option<Arc<Decode>>,
+}
+
+impl CustomInstruction {
+    pub fn new(
+        mnemonic: &str,
+        operands: &[OperandKind],
+        encode: impl Fn(&[u16]) -> u16 + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            mnemonic: mnemonic.to_uppercase(),
+            operands: operands.to_vec(),
+            encode: Arc::new(encode),
+            decode: None,
+        }
+    }
+
+    /// Recognize the instruction in the low 12 bits of a word and return its
+    /// operand fields, which lets the disassembler write it back
+    pub fn decoder(
+        mut self,
+        decode: impl Fn(u16) -> Option<Vec<u16>> + Send + Sync + 'static,
+    ) -> Self {
+        self.decode = Some(Arc::new(decode));
+        self
+    }
+
+    /// Upper case mnemonic
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    pub fn operands(&self) -> &[OperandKind] {
+        &self.operands
+    }
+
+    /// Read the operands of the signature and encode the word
+    pub fn encode(&self, operands: &mut Operands) -> Result<u16> {
+        let mut fields = Vec::with_capacity(self.operands.len());
+        for kind in &self.operands {
+            fields.push(match kind {
+                OperandKind::Reg => operands.reg()? as u16,
+                OperandKind::Imm(bits) => operands.imm(*bits)?,
+                OperandKind::Unsigned(bits) => operands.unsigned(*bits)?,
+                OperandKind::PcOffset9 => {
+                    let label = operands.label()?;
+                    operands.pc_offset(&label, RelocationKind::Pc9)?
+                }
+                OperandKind::PcOffset11 => {
+                    let label = operands.label()?;
+                    operands.pc_offset(&label, RelocationKind::Pc11)?
+                }
+            });
+        }
+
+        let bits = (self.encode)(&fields);
+        if bits >> 12 != 0 {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                format!(
+                    "{} encoded x{bits:04X}, which does not fit below the opcode",
+                    self.mnemonic
+                ),
+            ));
+        }
+        Ok(RESERVED_OPCODE << 12 | bits)
+    }
+
+    /// Operand fields of `word` if it is this instruction
+    ///
+    /// Fields that do not fit their operand are rejected, so the result
+    /// always assembles back.
+    pub fn decode(&self, word: u16) -> Option<Vec<u16>> {
+        if word >> 12 != RESERVED_OPCODE {
+            return None;
+        }
+        let fields = self.decode.as_ref()?(word & 0x0fff)?;
+        let fits = fields.len() == self.operands.len()
+            && fields
+                .iter()
+                .zip(&self.operands)
+                .all(|(field, kind)| field >> kind.bits() == 0);
+        fits.then_some(fields)
+    }
+
+    /// Addresses named by the PC offsets in `fields` of the instruction at `addr`
+    pub fn targets(&self, fields: &[u16], addr: u16) -> Vec<u16> {
+        self.operands
+            .iter()
+            .zip(fields)
+            .filter_map(|(kind, field)| {
+                let bits = kind.offset_bits()?;
+                Some(
+                    addr.wrapping_add(1)
+                        .wrapping_add(sign_extend(*field, bits) as u16),
+                )
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for CustomInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomInstruction")
+            .field("mnemonic", &self.mnemonic)
+            .field("operands", &self.operands)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Custom instructions added to the LC-3
+///
+/// Register one with
+/// [`AssemblerOptionsBuilder::extension`](crate::options::AssemblerOptionsBuilder::extension);
+/// [`Extension`] collects instructions built from closures.
+pub trait IsaExtension: Send + Sync {
+    /// Name given in errors about the extension, its type name by default
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    fn instructions(&self) -> &[CustomInstruction];
+}
+
+/// An extension listing its instructions
+#[derive(Clone, Debug, Default)]
+pub struct Extension {
+    name: Option<String>,
+    instructions: Vec<CustomInstruction>,
+}
+
+impl Extension {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An extension known as `name` in errors
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: Some(name.to_owned()),
+            ..Self::default()
+        }
+    }
+
+    pub fn instruction(mut self, instruction: CustomInstruction) -> Self {
+        self.instructions.push(instruction);
+        self
+    }
+}
+
+impl IsaExtension for Extension {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("unnamed extension")
+    }
+
+    fn instructions(&self) -> &[CustomInstruction] {
+        &self.instructions
+    }
+}
+
+/// Extensions registered with the assembler, equal when they are the same ones
+#[derive(Clone, Default)]
+pub struct Extensions(Vec<Arc<dyn IsaExtension>>);
+
+impl Extensions {
+    pub fn push(&mut self, extension: impl IsaExtension + 'static) {
+        self.0.push(Arc::new(extension));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = &CustomInstruction> {
+        self.0.iter().flat_map(|extension| extension.instructions())
+    }
+
+    /// The instruction spelled `mnemonic` in any case, the first registered wins
+    ///
+    /// Mnemonics of the target and directives are never looked up here.
+    pub fn find(&self, mnemonic: &str) -> Option<&CustomInstruction> {
+        let mnemonic = mnemonic.to_uppercase();
+        self.instructions().find(|i| i.mnemonic == mnemonic)
+    }
+
+    /// Reject mnemonics that read as a built-in opcode, directive or operand
+    ///
+    /// Those words are never looked up here, so the instruction could not be used.
+    pub fn check(&self) -> Result<()> {
+        for extension in &self.0 {
+            for instruction in extension.instructions() {
+                if !matches!(Token::parse(&instruction.mnemonic), Ok(Token::Label(_))) {
+                    return Err(Error::with_message(
+                        ErrorKind::ParseOpCodeError,
+                        format!(
+                            "{} of {} collides with a built-in opcode, directive or operand",
+                            instruction.mnemonic,
+                            extension.name()
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The instruction encoded in `word` and its operand fields
+    pub fn decode(&self, word: u16) -> Option<(&CustomInstruction, Vec<u16>)> {
+        self.instructions()
+            .find_map(|i| i.decode(word).map(|fields| (i, fields)))
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.instructions().map(CustomInstruction::mnemonic))
+            .finish()
+    }
+}
+
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(&other.0).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, options::AssemblerOptions, target::Isa};
+
+    fn extension() -> Extension {
+        use OperandKind::*;
+
+        Extension::new()
+            .instruction(
+                CustomInstruction::new("MUL", &[Reg, Reg, Reg], |f| f[0] << 9 | f[1] << 6 | f[2])
+                    .decoder(|bits| {
+                        (bits & 0x38 == 0).then(|| vec![bits >> 9, bits >> 6 & 7, bits & 7])
+                    }),
+            )
+            .instruction(CustomInstruction::new("DJNZ", &[Reg, PcOffset9], |f| {
+                f[0] << 9 | f[1]
+            }))
+    }
+
+    #[test]
+    fn test_custom_instructions() {
+        let source = ".ORIG x3000\nLOOP mul R0, R1, R2\nDJNZ R3, LOOP\nHALT\n.END\n";
+        let options = AssemblerOptions::builder().extension(extension()).build();
+        let program = assemble(source, &options).unwrap();
+        assert_eq!(program.image(), vec![0x3000, 0xd042, 0xd7fe, 0xf025]);
+
+        assert_eq!(options.extensions.decode(0xd042).unwrap().1, vec![0, 1, 2]);
+        assert!(options.extensions.decode(0xd7fe).is_none());
+
+        let options = AssemblerOptions::builder()
+            .extension(extension())
+            .isa(Isa::Lc3b)
+            .build();
+        let diagnostics = assemble(source, &options).unwrap_err();
+        assert_eq!(
+            diagnostics.to_string(),
+            "<source>:2: [ParseOpCodeError] MUL is a custom instruction, the lc3b target has no opcode for it"
+        );
+
+        let wide = CustomInstruction::new("WIDE", &[OperandKind::Unsigned(4)], |f| f[0] << 12);
+        let options = AssemblerOptions::builder()
+            .extension(Extension::new().instruction(wide))
+            .build();
+        let diagnostics = assemble(".ORIG x3000\nWIDE #1\n.END\n", &options).unwrap_err();
+        assert_eq!(
+            diagnostics.to_string(),
+            "<source>:2: [ValueError] WIDE encoded x1000, which does not fit below the opcode"
+        );
+
+        for mnemonic in ["halt", ".FILL", "R1"] {
+            let clash = CustomInstruction::new(mnemonic, &[], |_| 0);
+            let options = AssemblerOptions::builder()
+                .extension(extension())
+                .extension(Extension::named("clash").instruction(clash))
+                .build();
+            let diagnostics = assemble(".ORIG x3000\nHALT\n.END\n", &options).unwrap_err();
+            assert_eq!(
+                diagnostics.to_string(),
+                format!(
+                    "<source>: [ParseOpCodeError] {} of clash collides with a built-in opcode, directive or operand",
+                    mnemonic.to_uppercase()
+                )
+            );
+        }
+    }
+}
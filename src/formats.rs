@@ -0,0 +1,451 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Bytes per data record in Intel HEX and S-record output
+const RECORD_LEN: usize = 16;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Big-endian words, origin first (.obj)
+    Obj,
+    /// One hex word per line, origin first, as used by lc3tools (.hex)
+    Hex,
+    /// Headerless raw words (.bin)
+    Bin,
+    /// Intel HEX, byte addressed (.ihx)
+    Ihex,
+    /// Motorola S-record, byte addressed (.srec)
+    Srec,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Obj => "obj",
+            OutputFormat::Hex => "hex",
+            OutputFormat::Bin => "bin",
+            OutputFormat::Ihex => "ihx",
+            OutputFormat::Srec => "srec",
+        }
+    }
+}
+
+/// Write an image (origin followed by words) in the given format
+///
+/// `word_size` is the number of addresses a word takes, as in
+/// [`Target::word_size`](crate::target::Target::word_size), which places
+/// the byte addressed formats.
+pub fn write_image<W: Write>(
+    w: &mut W,
+    format: OutputFormat,
+    endian: Endian,
+    word_size: u16,
+    bin: &[u16],
+) -> Result<()> {
+    match format {
+        OutputFormat::Obj => write_obj(w, bin),
+        OutputFormat::Hex => write_hex(w, bin),
+        OutputFormat::Bin => write_bin(w, endian, bin),
+        OutputFormat::Ihex => write_ihex(w, word_size, bin),
+        OutputFormat::Srec => write_srec(w, word_size, bin),
+    }
+}
+
+/// Read an image back in the given format
+///
+/// Raw binaries carry no origin, so `origin` is used for them instead.
+pub fn read_image<R: Read>(
+    r: R,
+    format: OutputFormat,
+    endian: Endian,
+    word_size: u16,
+    origin: u16,
+) -> Result<Vec<u16>> {
+    match format {
+        OutputFormat::Obj => read_obj(r),
+        OutputFormat::Hex => read_hex(r),
+        OutputFormat::Bin => read_bin(r, endian, origin),
+        OutputFormat::Ihex => read_ihex(r, word_size),
+        OutputFormat::Srec => read_srec(r, word_size),
+    }
+}
+
+pub fn write_obj<W: Write>(w: &mut W, bin: &[u16]) -> Result<()> {
+    for &word in bin {
+        w.write_u16::<BigEndian>(word)?;
+    }
+    Ok(())
+}
+
+pub fn read_obj<R: Read>(r: R) -> Result<Vec<u16>> {
+    read_words(r, Endian::Big)
+}
+
+/// All words of `r`, failing on a trailing odd byte
+fn read_words<R: Read>(mut r: R, endian: Endian) -> Result<Vec<u16>> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    if bytes.len() % 2 != 0 {
+        return Err(Error::with_message(
+            ErrorKind::ValueError,
+            format!("truncated word at byte {}", bytes.len() - 1),
+        ));
+    }
+
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|pair| match endian {
+            Endian::Big => u16::from_be_bytes([pair[0], pair[1]]),
+            Endian::Little => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .collect())
+}
+
+pub fn write_hex<W: Write>(w: &mut W, bin: &[u16]) -> Result<()> {
+    for word in bin {
+        writeln!(w, "{word:04X}")?;
+    }
+    Ok(())
+}
+
+pub fn read_hex<R: Read>(r: R) -> Result<Vec<u16>> {
+    let mut bin = Vec::new();
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            bin.push(u16::from_str_radix(line, 16)?);
+        }
+    }
+    Ok(bin)
+}
+
+/// Raw words without the origin
+pub fn write_bin<W: Write>(w: &mut W, endian: Endian, bin: &[u16]) -> Result<()> {
+    for &word in bin.iter().skip(1) {
+        match endian {
+            Endian::Big => w.write_u16::<BigEndian>(word)?,
+            Endian::Little => w.write_u16::<LittleEndian>(word)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn read_bin<R: Read>(r: R, endian: Endian, origin: u16) -> Result<Vec<u16>> {
+    let mut bin = vec![origin];
+    bin.extend(read_words(r, endian)?);
+    Ok(bin)
+}
+
+pub fn write_ihex<W: Write>(w: &mut W, word_size: u16, bin: &[u16]) -> Result<()> {
+    let (mut addr, bytes) = to_bytes(bin, word_size)?;
+    let mut rest = &bytes[..];
+    let mut upper = None;
+
+    while !rest.is_empty() {
+        // Records must not cross a 64K boundary
+        let len = RECORD_LEN
+            .min(0x10000 - (addr & 0xffff) as usize)
+            .min(rest.len());
+        let (chunk, tail) = rest.split_at(len);
+
+        if upper != Some(addr >> 16) {
+            upper = Some(addr >> 16);
+            let ext = ((addr >> 16) as u16).to_be_bytes();
+            write_ihex_record(w, 0, 0x04, &ext)?;
+        }
+        write_ihex_record(w, addr as u16, 0x00, chunk)?;
+
+        addr += len as u32;
+        rest = tail;
+    }
+    write_ihex_record(w, 0, 0x01, &[])
+}
+
+fn write_ihex_record<W: Write>(w: &mut W, addr: u16, kind: u8, data: &[u8]) -> Result<()> {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&addr.to_be_bytes());
+    record.push(kind);
+    record.extend_from_slice(data);
+
+    let sum = record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    record.push(sum.wrapping_neg());
+
+    writeln!(w, ":{}", to_hex_string(&record))?;
+    Ok(())
+}
+
+pub fn read_ihex<R: Read>(r: R, word_size: u16) -> Result<Vec<u16>> {
+    let mut upper = 0u32;
+    let mut data = Vec::new();
+
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = line
+            .strip_prefix(':')
+            .ok_or(Error::new(ErrorKind::SyntaxError))
+            .and_then(from_hex_string)?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(Error::new(ErrorKind::SyntaxError));
+        }
+        if record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+            return Err(Error::new(ErrorKind::ValueError));
+        }
+
+        let addr = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let payload = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => data.push((upper | addr, payload.to_vec())),
+            0x01 => break,
+            0x04 if payload.len() == 2 => {
+                upper = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 16
+            }
+            _ => return Err(Error::new(ErrorKind::SyntaxError)),
+        }
+    }
+
+    from_bytes(data, word_size)
+}
+
+pub fn write_srec<W: Write>(w: &mut W, word_size: u16, bin: &[u16]) -> Result<()> {
+    let (origin, bytes) = to_bytes(bin, word_size)?;
+
+    write_srec_record(w, '0', &[0, 0], b"LC3")?;
+    let mut count = 0u32;
+    for (i, chunk) in bytes.chunks(RECORD_LEN).enumerate() {
+        let addr = origin + (i * RECORD_LEN) as u32;
+        write_srec_record(w, '2', &addr.to_be_bytes()[1..], chunk)?;
+        count += 1;
+    }
+    if count <= 0xffff {
+        write_srec_record(w, '5', &(count as u16).to_be_bytes(), &[])?;
+    } else {
+        write_srec_record(w, '6', &count.to_be_bytes()[1..], &[])?;
+    }
+    write_srec_record(w, '8', &origin.to_be_bytes()[1..], &[])
+}
+
+fn write_srec_record<W: Write>(w: &mut W, kind: char, addr: &[u8], data: &[u8]) -> Result<()> {
+    let mut record = vec![(addr.len() + data.len() + 1) as u8];
+    record.extend_from_slice(addr);
+    record.extend_from_slice(data);
+
+    let sum = record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    record.push(!sum);
+
+    writeln!(w, "S{kind}{}", to_hex_string(&record))?;
+    Ok(())
+}
+
+pub fn read_srec<R: Read>(r: R, word_size: u16) -> Result<Vec<u16>> {
+    let mut data = Vec::new();
+
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(Error::new(ErrorKind::SyntaxError));
+        }
+        let kind = chars.next().ok_or(Error::new(ErrorKind::SyntaxError))?;
+        let record = from_hex_string(chars.as_str())?;
+        if record.is_empty() || record.len() != record[0] as usize + 1 {
+            return Err(Error::new(ErrorKind::SyntaxError));
+        }
+        if record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0xff {
+            return Err(Error::new(ErrorKind::ValueError));
+        }
+
+        let addr_len = match kind {
+            '1' => 2,
+            '2' => 3,
+            '3' => 4,
+            _ => continue,
+        };
+        let addr = record[1..1 + addr_len]
+            .iter()
+            .fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        data.push((addr, record[1 + addr_len..record.len() - 1].to_vec()));
+    }
+
+    from_bytes(data, word_size)
+}
+
+/// Split an image into its byte origin and big-endian bytes
+fn to_bytes(bin: &[u16], word_size: u16) -> Result<(u32, Vec<u8>)> {
+    let (&origin, words) = bin
+        .split_first()
+        .ok_or(Error::new(ErrorKind::UnexpectedEof))?;
+    let bytes = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    Ok((origin as u32 * bytes_per_address(word_size), bytes))
+}
+
+/// Reassemble addressed byte records into an image, gaps are zero filled
+fn from_bytes(mut data: Vec<(u32, Vec<u8>)>, word_size: u16) -> Result<Vec<u16>> {
+    data.sort_by_key(|(addr, _)| *addr);
+    let start = data
+        .first()
+        .map(|(addr, _)| *addr)
+        .ok_or(Error::new(ErrorKind::UnexpectedEof))?;
+    if !start.is_multiple_of(2) {
+        return Err(Error::new(ErrorKind::ValueError));
+    }
+
+    let mut bytes = Vec::new();
+    for (addr, chunk) in data {
+        let offset = (addr - start) as usize;
+        if bytes.len() < offset {
+            bytes.resize(offset, 0);
+        }
+        bytes.truncate(offset);
+        bytes.extend_from_slice(&chunk);
+    }
+    if !bytes.len().is_multiple_of(2) {
+        return Err(Error::new(ErrorKind::ValueError));
+    }
+
+    let mut bin = vec![(start / bytes_per_address(word_size)) as u16];
+    bin.extend(bytes.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])));
+    Ok(bin)
+}
+
+/// Bytes in each address of a target whose words take `word_size` addresses
+fn bytes_per_address(word_size: u16) -> u32 {
+    2 / word_size as u32
+}
+
+fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+fn from_hex_string(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(Error::new(ErrorKind::SyntaxError));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: [u16; 4] = [0x3000, 0xe002, 0xf022, 0xf025];
+
+    fn round_trip(format: OutputFormat, endian: Endian, bin: &[u16]) -> Vec<u16> {
+        let mut out = Vec::new();
+        write_image(&mut out, format, endian, 1, bin).unwrap();
+        read_image(&out[..], format, endian, 1, bin[0]).unwrap()
+    }
+
+    #[test]
+    fn test_obj() {
+        let mut out = Vec::new();
+        write_obj(&mut out, &IMAGE).unwrap();
+        assert_eq!(out, vec![0x30, 0x00, 0xe0, 0x02, 0xf0, 0x22, 0xf0, 0x25]);
+        assert_eq!(round_trip(OutputFormat::Obj, Endian::Big, &IMAGE), IMAGE);
+
+        let error = read_obj(&[0x30, 0x00, 0xe0][..]).unwrap_err();
+        assert_eq!(error.to_string(), "[ValueError] truncated word at byte 2");
+    }
+
+    #[test]
+    fn test_hex() {
+        let mut out = Vec::new();
+        write_hex(&mut out, &IMAGE).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "3000\nE002\nF022\nF025\n");
+        assert_eq!(round_trip(OutputFormat::Hex, Endian::Big, &IMAGE), IMAGE);
+    }
+
+    #[test]
+    fn test_bin() {
+        let mut out = Vec::new();
+        write_bin(&mut out, Endian::Little, &IMAGE).unwrap();
+        assert_eq!(out, vec![0x02, 0xe0, 0x22, 0xf0, 0x25, 0xf0]);
+        assert_eq!(round_trip(OutputFormat::Bin, Endian::Big, &IMAGE), IMAGE);
+        assert_eq!(round_trip(OutputFormat::Bin, Endian::Little, &IMAGE), IMAGE);
+    }
+
+    #[test]
+    fn test_ihex() {
+        let mut out = Vec::new();
+        write_ihex(&mut out, 1, &IMAGE).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            ":020000040000FA\n:06600000E002F022F02591\n:00000001FF\n"
+        );
+        assert_eq!(round_trip(OutputFormat::Ihex, Endian::Big, &IMAGE), IMAGE);
+
+        // Crosses into the upper 64K of byte addresses
+        let mut high = vec![0xfff0];
+        high.extend(0..32);
+        assert_eq!(round_trip(OutputFormat::Ihex, Endian::Big, &high), high);
+    }
+
+    #[test]
+    fn test_srec() {
+        let mut out = Vec::new();
+        write_srec(&mut out, 1, &IMAGE).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "S00600004C433337\nS20A006000E002F022F0258C\nS5030001FB\nS8040060009B\n"
+        );
+        assert_eq!(round_trip(OutputFormat::Srec, Endian::Big, &IMAGE), IMAGE);
+    }
+
+    #[test]
+    fn test_lc3b_byte_addresses() {
+        let mut out = Vec::new();
+        write_image(&mut out, OutputFormat::Ihex, Endian::Big, 2, &IMAGE).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            ":020000040000FA\n:06300000E002F022F025C1\n:00000001FF\n"
+        );
+        let mut out = Vec::new();
+        write_image(&mut out, OutputFormat::Srec, Endian::Big, 2, &IMAGE).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "S00600004C433337\nS20A003000E002F022F025BC\nS5030001FB\nS804003000CB\n"
+        );
+
+        for format in [OutputFormat::Ihex, OutputFormat::Srec] {
+            let mut out = Vec::new();
+            write_image(&mut out, format, Endian::Big, 2, &IMAGE).unwrap();
+            assert_eq!(
+                read_image(&out[..], format, Endian::Big, 2, 0).unwrap(),
+                IMAGE
+            );
+        }
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let res = read_ihex(&b":06600000E002F022F02592\n"[..], 1).map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::ValueError));
+    }
+}
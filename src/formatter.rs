@@ -0,0 +1,238 @@
+use crate::{
+    enums::{Parseable, Token},
+    error::{Error, ErrorKind},
+    extension::Extensions,
+    program::{Diagnostic, Diagnostics},
+    utils::{split_comment, tokenize_with},
+};
+
+/// Minimum column instructions start at
+const INDENT: usize = 4;
+
+/// Trailing comments are aligned up to this column, longer lines get a single gap
+const MAX_COMMENT_COLUMN: usize = 48;
+
+/// A source line split into its parts
+#[derive(Default)]
+struct Line {
+    label: Option<String>,
+    code: Option<String>,
+    comment: Option<String>,
+    indented: bool,
+}
+
+/// Reformat source with aligned columns and canonical mnemonics
+///
+/// Labels stay in the first column, instructions start at a common column,
+/// trailing comments are aligned and full-line comments keep whether they
+/// were indented. Every line keeps its line number.
+pub fn format_source(name: &str, source: &str) -> Result<String, Diagnostics> {
+    format_source_with(name, source, &Extensions::default())
+}
+
+/// Like [`format_source`], accepting the instructions of `extensions`
+pub fn format_source_with(
+    name: &str,
+    source: &str,
+    extensions: &Extensions,
+) -> Result<String, Diagnostics> {
+    let mut lines = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (i, text) in source.lines().enumerate() {
+        match parse_line(text, extensions) {
+            Ok(line) => lines.push(line),
+            Err(error) => diagnostics.push(Diagnostic {
+                file: name.to_owned(),
+                line: Some(i + 1),
+                error,
+            }),
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(Diagnostics::from(diagnostics));
+    }
+
+    let code_column = lines
+        .iter()
+        .filter(|line| line.code.is_some())
+        .filter_map(|line| line.label.as_ref().map(|label| label.len() + 1))
+        .fold(INDENT, usize::max);
+
+    let mut formatted: Vec<String> = lines
+        .iter()
+        .map(|line| match (&line.label, &line.code) {
+            (Some(label), Some(code)) => format!("{label:<code_column$}{code}"),
+            (Some(label), None) => label.clone(),
+            (None, Some(code)) if code.starts_with(".ORIG") || code.starts_with(".END") => {
+                code.clone()
+            }
+            (None, Some(code)) => format!("{:code_column$}{code}", ""),
+            (None, None) if line.indented && line.comment.is_some() => " ".repeat(code_column),
+            (None, None) => String::new(),
+        })
+        .collect();
+
+    let comment_column = formatted
+        .iter()
+        .zip(&lines)
+        .filter(|(text, line)| line.comment.is_some() && !text.trim().is_empty())
+        .map(|(text, _)| text.len() + 2)
+        .filter(|column| *column <= MAX_COMMENT_COLUMN)
+        .max()
+        .unwrap_or_default();
+
+    for (text, line) in formatted.iter_mut().zip(&lines) {
+        if let Some(comment) = &line.comment {
+            if !text.trim().is_empty() {
+                let column = comment_column.max(text.len() + 2);
+                *text = format!("{text:column$}");
+            }
+            text.push_str(comment);
+        }
+    }
+
+    let mut output = formatted.join("\n");
+    output.push('\n');
+    Ok(output)
+}
+
+fn parse_line(text: &str, extensions: &Extensions) -> Result<Line, Error> {
+    let (code, comment) = split_comment(text);
+    let mut line = Line {
+        comment: comment.map(|c| c.trim_end().to_owned()),
+        indented: text.starts_with(char::is_whitespace),
+        ..Default::default()
+    };
+
+    let code = code.trim();
+    if code.is_empty() {
+        return Ok(line);
+    }
+    // Reject anything the assembler would not lex
+    let is_custom = |word: &str| extensions.find(word).is_some();
+    tokenize_with(code, &is_custom)?;
+    let parse = |word: &str| -> Result<Token, Error> {
+        Ok(match Token::parse(word)? {
+            Token::Label(_) if is_custom(word) => Token::Custom(word.to_uppercase()),
+            token => token,
+        })
+    };
+
+    let mut rest = code;
+    let (first, after) = split_word(rest);
+    if let Token::Label(label) = parse(first)? {
+        line.label = Some(label);
+        rest = after;
+    }
+    if rest.is_empty() {
+        return Ok(line);
+    }
+
+    let (word, operands) = split_word(rest);
+    let code = match parse(word)? {
+        token @ (Token::Op(_) | Token::Custom(_)) => {
+            let operands: Vec<_> = operands
+                .split(',')
+                .map(str::trim)
+                .filter(|operand| !operand.is_empty())
+                .map(format_operand)
+                .collect();
+            let mnemonic = match token {
+                Token::Custom(mnemonic) => mnemonic,
+                _ => format_mnemonic(word),
+            };
+            join(&mnemonic, &operands.join(", "))
+        }
+        Token::Dir(_) => join(&word.to_uppercase(), operands),
+        _ => return Err(Error::new(ErrorKind::SyntaxError)),
+    };
+    line.code = Some(code);
+
+    Ok(line)
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn join(mnemonic: &str, operands: &str) -> String {
+    if operands.is_empty() {
+        mnemonic.to_owned()
+    } else {
+        format!("{mnemonic:<5} {operands}")
+    }
+}
+
+/// Upper case mnemonics, with BR condition flags in lower case as in `BRnzp`
+fn format_mnemonic(word: &str) -> String {
+    let upper = word.to_uppercase();
+    match upper.strip_prefix("BR") {
+        Some(flags) => format!("BR{}", flags.to_lowercase()),
+        None => upper,
+    }
+}
+
+fn format_operand(operand: &str) -> String {
+    match Token::parse(operand) {
+        Ok(Token::Reg(_)) => operand.to_uppercase(),
+        _ => operand.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, AssemblerOptions};
+
+    #[test]
+    fn test_format_source() {
+        let source = "\
+; Say hello
+.orig x3000
+  lea r0,HELLO ; load it
+\tputs
+loop brnzp loop
+        ; spin
+HELLO .stringz \"Hi; there\"
+DONE
+.end";
+        let expected = "\
+; Say hello
+.ORIG x3000
+      LEA   R0, HELLO  ; load it
+      PUTS
+loop  BRnzp loop
+      ; spin
+HELLO .STRINGZ \"Hi; there\"
+DONE
+.END
+";
+        let formatted = format_source("hello.asm", source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source("hello.asm", &formatted).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_source_assembles() {
+        let source = std::fs::read_to_string("asm/2048.ggnm").unwrap();
+        let formatted = format_source("2048.ggnm", &source).unwrap();
+
+        // 2048 imports from the standard library, so compare the objects
+        let options = AssemblerOptions::builder().relocatable(true).build();
+        let expected = assemble(&source, &options).unwrap();
+        let program = assemble(&formatted, &options).unwrap();
+        assert_eq!(program.image(), expected.image());
+        assert_eq!(program.object(), expected.object());
+        assert_eq!(formatted.lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn test_format_source_errors() {
+        let diagnostics = format_source("bad.asm", ".ORIG x3000\nADD R0, xZZ\n").unwrap_err();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.line, Some(2));
+    }
+}
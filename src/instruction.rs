@@ -0,0 +1,789 @@
+use std::fmt;
+
+use num_traits::FromPrimitive;
+
+use crate::enums::Register;
+
+/// Second source operand of ADD and AND
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Reg(Register),
+    /// 5-bit signed immediate
+    Imm(i16),
+}
+
+/// A single LC-3 instruction with its fields decoded
+///
+/// Offsets and immediates are sign-extended, PC-relative offsets count from
+/// the word after the instruction. Words that no instruction encodes to,
+/// such as the reserved `1101` opcode or set bits in unused fields, decode
+/// to [`Instruction::Data`] so that `decode(w).encode() == w` for every word.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        offset: i16,
+    },
+    Add {
+        dr: Register,
+        sr1: Register,
+        src: Operand,
+    },
+    Ld {
+        dr: Register,
+        offset: i16,
+    },
+    St {
+        sr: Register,
+        offset: i16,
+    },
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: Register,
+    },
+    And {
+        dr: Register,
+        sr1: Register,
+        src: Operand,
+    },
+    Ldr {
+        dr: Register,
+        base: Register,
+        offset: i16,
+    },
+    Str {
+        sr: Register,
+        base: Register,
+        offset: i16,
+    },
+    Rti,
+    Not {
+        dr: Register,
+        sr: Register,
+    },
+    Ldi {
+        dr: Register,
+        offset: i16,
+    },
+    Sti {
+        sr: Register,
+        offset: i16,
+    },
+    /// `RET` is `JMP R7`
+    Jmp {
+        base: Register,
+    },
+    Lea {
+        dr: Register,
+        offset: i16,
+    },
+    Trap {
+        vector: u8,
+    },
+    Data(u16),
+}
+
+impl Instruction {
+    pub fn encode(&self) -> u16 {
+        match *self {
+            Self::Br { n, z, p, offset } => {
+                (n as u16) << 11 | (z as u16) << 10 | (p as u16) << 9 | field(offset, 9)
+            }
+            Self::Add { dr, sr1, src } => 0x1000 | reg(dr, 9) | reg(sr1, 6) | operand(src),
+            Self::Ld { dr, offset } => 0x2000 | reg(dr, 9) | field(offset, 9),
+            Self::St { sr, offset } => 0x3000 | reg(sr, 9) | field(offset, 9),
+            Self::Jsr { offset } => 0x4800 | field(offset, 11),
+            Self::Jsrr { base } => 0x4000 | reg(base, 6),
+            Self::And { dr, sr1, src } => 0x5000 | reg(dr, 9) | reg(sr1, 6) | operand(src),
+            Self::Ldr { dr, base, offset } => 0x6000 | reg(dr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Str { sr, base, offset } => 0x7000 | reg(sr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Rti => 0x8000,
+            Self::Not { dr, sr } => 0x903f | reg(dr, 9) | reg(sr, 6),
+            Self::Ldi { dr, offset } => 0xa000 | reg(dr, 9) | field(offset, 9),
+            Self::Sti { sr, offset } => 0xb000 | reg(sr, 9) | field(offset, 9),
+            Self::Jmp { base } => 0xc000 | reg(base, 6),
+            Self::Lea { dr, offset } => 0xe000 | reg(dr, 9) | field(offset, 9),
+            Self::Trap { vector } => 0xf000 | vector as u16,
+            Self::Data(word) => word,
+        }
+    }
+
+    pub fn decode(word: u16) -> Self {
+        let instruction = decode_fields(word);
+        if instruction.encode() == word {
+            instruction
+        } else {
+            Self::Data(word)
+        }
+    }
+
+    /// Decode `word` the way the hardware does, ignoring bits no field uses
+    ///
+    /// Only the reserved `1101` opcode decodes to [`Instruction::Data`].
+    pub fn decode_lossy(word: u16) -> Self {
+        decode_fields(word)
+    }
+
+    /// Address a PC-relative instruction at `addr` refers to
+    pub fn target(&self, addr: u16) -> Option<u16> {
+        match *self {
+            Self::Br { offset, .. }
+            | Self::Ld { offset, .. }
+            | Self::St { offset, .. }
+            | Self::Jsr { offset }
+            | Self::Ldi { offset, .. }
+            | Self::Sti { offset, .. }
+            | Self::Lea { offset, .. } => Some(addr.wrapping_add(1).wrapping_add(offset as u16)),
+            _ => None,
+        }
+    }
+
+    /// Mnemonic as written in source, `BR` carries its condition flags
+    pub fn mnemonic(&self) -> String {
+        let name = match self {
+            Self::Br { n, z, p, .. } => {
+                let mut name = String::from("BR");
+                for (set, flag) in [(n, 'n'), (z, 'z'), (p, 'p')] {
+                    if *set {
+                        name.push(flag);
+                    }
+                }
+                return name;
+            }
+            Self::Add { .. } => "ADD",
+            Self::Ld { .. } => "LD",
+            Self::St { .. } => "ST",
+            Self::Jsr { .. } => "JSR",
+            Self::Jsrr { .. } => "JSRR",
+            Self::And { .. } => "AND",
+            Self::Ldr { .. } => "LDR",
+            Self::Str { .. } => "STR",
+            Self::Rti => "RTI",
+            Self::Not { .. } => "NOT",
+            Self::Ldi { .. } => "LDI",
+            Self::Sti { .. } => "STI",
+            Self::Jmp { base: Register::R7 } => "RET",
+            Self::Jmp { .. } => "JMP",
+            Self::Lea { .. } => "LEA",
+            Self::Trap { vector: 0x20 } => "GETC",
+            Self::Trap { vector: 0x21 } => "OUT",
+            Self::Trap { vector: 0x22 } => "PUTS",
+            Self::Trap { vector: 0x23 } => "IN",
+            Self::Trap { vector: 0x24 } => "PUTSP",
+            Self::Trap { vector: 0x25 } => "HALT",
+            Self::Trap { .. } => "TRAP",
+            Self::Data(_) => ".FILL",
+        };
+        name.to_owned()
+    }
+}
+
+/// Assembly text with offsets written as constants, e.g. `LD R0, #-3`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+        match *self {
+            Self::Br { offset, .. } | Self::Jsr { offset } => write!(f, "{mnemonic} #{offset}"),
+            Self::Add { dr, sr1, src } | Self::And { dr, sr1, src } => match src {
+                Operand::Reg(sr2) => write!(f, "{mnemonic} {dr:?}, {sr1:?}, {sr2:?}"),
+                Operand::Imm(imm) => write!(f, "{mnemonic} {dr:?}, {sr1:?}, #{imm}"),
+            },
+            Self::Ld { dr: r, offset }
+            | Self::St { sr: r, offset }
+            | Self::Ldi { dr: r, offset }
+            | Self::Sti { sr: r, offset }
+            | Self::Lea { dr: r, offset } => write!(f, "{mnemonic} {r:?}, #{offset}"),
+            Self::Ldr {
+                dr: r,
+                base,
+                offset,
+            }
+            | Self::Str {
+                sr: r,
+                base,
+                offset,
+            } => {
+                write!(f, "{mnemonic} {r:?}, {base:?}, #{offset}")
+            }
+            Self::Not { dr, sr } => write!(f, "{mnemonic} {dr:?}, {sr:?}"),
+            Self::Jsrr { base } => write!(f, "{mnemonic} {base:?}"),
+            Self::Jmp { base: Register::R7 } | Self::Rti => write!(f, "{mnemonic}"),
+            Self::Jmp { base } => write!(f, "{mnemonic} {base:?}"),
+            Self::Trap {
+                vector: 0x20..=0x25,
+            } => write!(f, "{mnemonic}"),
+            Self::Trap { vector } => write!(f, "{mnemonic} x{vector:02X}"),
+            Self::Data(word) => write!(f, "{mnemonic} x{word:04X}"),
+        }
+    }
+}
+
+/// Shift performed by the LC-3b `SHF` opcode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shift {
+    Lshf,
+    Rshfl,
+    Rshfa,
+}
+
+/// A single LC-3b instruction with its fields decoded
+///
+/// The LC-3b reuses the opcodes of `LD`, `ST`, `LDR`, `STR`, `NOT` and the
+/// reserved `1101` for its own instructions and leaves those of `LDI` and
+/// `STI` unused; the rest encode as on the LC-3 and are carried as
+/// [`Lc3bInstruction::Lc3`]. `NOT` is `XOR` with an immediate of -1. As for
+/// [`Instruction`], `decode(w).encode() == w` for every word.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lc3bInstruction {
+    /// The offset counts bytes
+    Ldb {
+        dr: Register,
+        base: Register,
+        offset: i16,
+    },
+    /// The offset counts bytes
+    Stb {
+        sr: Register,
+        base: Register,
+        offset: i16,
+    },
+    /// The offset counts words
+    Ldw {
+        dr: Register,
+        base: Register,
+        offset: i16,
+    },
+    /// The offset counts words
+    Stw {
+        sr: Register,
+        base: Register,
+        offset: i16,
+    },
+    Xor {
+        dr: Register,
+        sr1: Register,
+        src: Operand,
+    },
+    /// Shift by a 4-bit amount
+    Shf {
+        shift: Shift,
+        dr: Register,
+        sr: Register,
+        amount: u16,
+    },
+    /// `BR`, `ADD`, `JSR`, `JSRR`, `AND`, `RTI`, `JMP`, `LEA` or `TRAP`
+    Lc3(Instruction),
+    Data(u16),
+}
+
+impl Lc3bInstruction {
+    pub fn encode(&self) -> u16 {
+        match *self {
+            Self::Ldb { dr, base, offset } => 0x2000 | reg(dr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Stb { sr, base, offset } => 0x3000 | reg(sr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Ldw { dr, base, offset } => 0x6000 | reg(dr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Stw { sr, base, offset } => 0x7000 | reg(sr, 9) | reg(base, 6) | field(offset, 6),
+            Self::Xor { dr, sr1, src } => 0x9000 | reg(dr, 9) | reg(sr1, 6) | operand(src),
+            Self::Shf {
+                shift,
+                dr,
+                sr,
+                amount,
+            } => {
+                let kind = match shift {
+                    Shift::Lshf => 0b00,
+                    Shift::Rshfl => 0b01,
+                    Shift::Rshfa => 0b11,
+                };
+                0xd000 | reg(dr, 9) | reg(sr, 6) | kind << 4 | amount & 0xf
+            }
+            Self::Lc3(instruction) => instruction.encode(),
+            Self::Data(word) => word,
+        }
+    }
+
+    pub fn decode(word: u16) -> Self {
+        let r9 = register(word, 9);
+        let r6 = register(word, 6);
+        let offset6 = sign_extend(word, 6);
+        let instruction = match word >> 12 {
+            0b0010 => Self::Ldb {
+                dr: r9,
+                base: r6,
+                offset: offset6,
+            },
+            0b0011 => Self::Stb {
+                sr: r9,
+                base: r6,
+                offset: offset6,
+            },
+            0b0110 => Self::Ldw {
+                dr: r9,
+                base: r6,
+                offset: offset6,
+            },
+            0b0111 => Self::Stw {
+                sr: r9,
+                base: r6,
+                offset: offset6,
+            },
+            0b1001 => Self::Xor {
+                dr: r9,
+                sr1: r6,
+                src: if word & 0x20 != 0 {
+                    Operand::Imm(sign_extend(word, 5))
+                } else {
+                    Operand::Reg(register(word, 0))
+                },
+            },
+            0b1101 => {
+                let shift = match word >> 4 & 0b11 {
+                    0b00 => Shift::Lshf,
+                    0b01 => Shift::Rshfl,
+                    0b11 => Shift::Rshfa,
+                    _ => return Self::Data(word),
+                };
+                Self::Shf {
+                    shift,
+                    dr: r9,
+                    sr: r6,
+                    amount: word & 0xf,
+                }
+            }
+            0b1010 | 0b1011 => Self::Data(word),
+            _ => match Instruction::decode(word) {
+                Instruction::Data(word) => Self::Data(word),
+                instruction => Self::Lc3(instruction),
+            },
+        };
+
+        if instruction.encode() == word {
+            instruction
+        } else {
+            Self::Data(word)
+        }
+    }
+
+    /// Mnemonic as written in source, `XOR` with -1 is `NOT`
+    pub fn mnemonic(&self) -> String {
+        let name = match self {
+            Self::Ldb { .. } => "LDB",
+            Self::Stb { .. } => "STB",
+            Self::Ldw { .. } => "LDW",
+            Self::Stw { .. } => "STW",
+            Self::Xor {
+                src: Operand::Imm(-1),
+                ..
+            } => "NOT",
+            Self::Xor { .. } => "XOR",
+            Self::Shf {
+                shift: Shift::Lshf, ..
+            } => "LSHF",
+            Self::Shf {
+                shift: Shift::Rshfl,
+                ..
+            } => "RSHFL",
+            Self::Shf {
+                shift: Shift::Rshfa,
+                ..
+            } => "RSHFA",
+            Self::Lc3(instruction) => return instruction.mnemonic(),
+            Self::Data(_) => ".FILL",
+        };
+        name.to_owned()
+    }
+}
+
+/// Assembly text with offsets written as constants, e.g. `LDB R0, R1, #-3`
+impl fmt::Display for Lc3bInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+        match *self {
+            Self::Ldb {
+                dr: r,
+                base,
+                offset,
+            }
+            | Self::Stb {
+                sr: r,
+                base,
+                offset,
+            }
+            | Self::Ldw {
+                dr: r,
+                base,
+                offset,
+            }
+            | Self::Stw {
+                sr: r,
+                base,
+                offset,
+            } => write!(f, "{mnemonic} {r:?}, {base:?}, #{offset}"),
+            Self::Xor {
+                dr,
+                sr1,
+                src: Operand::Imm(-1),
+            } => write!(f, "{mnemonic} {dr:?}, {sr1:?}"),
+            Self::Xor { dr, sr1, src } => match src {
+                Operand::Reg(sr2) => write!(f, "{mnemonic} {dr:?}, {sr1:?}, {sr2:?}"),
+                Operand::Imm(imm) => write!(f, "{mnemonic} {dr:?}, {sr1:?}, #{imm}"),
+            },
+            Self::Shf { dr, sr, amount, .. } => write!(f, "{mnemonic} {dr:?}, {sr:?}, #{amount}"),
+            Self::Lc3(instruction) => write!(f, "{instruction}"),
+            Self::Data(word) => write!(f, "{mnemonic} x{word:04X}"),
+        }
+    }
+}
+
+/// Sign-extend the low `bit_count` bits of `value`
+pub fn sign_extend(value: u16, bit_count: u16) -> i16 {
+    let shift = 16 - bit_count;
+    ((value << shift) as i16) >> shift
+}
+
+fn field(value: i16, bit_count: u16) -> u16 {
+    value as u16 & (0xffff >> (16 - bit_count))
+}
+
+fn reg(r: Register, shift: u16) -> u16 {
+    (r as u16) << shift
+}
+
+fn operand(src: Operand) -> u16 {
+    match src {
+        Operand::Reg(sr2) => sr2 as u16,
+        Operand::Imm(imm) => 0x20 | field(imm, 5),
+    }
+}
+
+fn register(word: u16, shift: u16) -> Register {
+    Register::from_u16((word >> shift) & 0b111).unwrap()
+}
+
+/// Decode by opcode without checking that unused bits are clear
+fn decode_fields(word: u16) -> Instruction {
+    let r9 = register(word, 9);
+    let r6 = register(word, 6);
+    let offset9 = sign_extend(word, 9);
+    let offset6 = sign_extend(word, 6);
+    let src = if word & 0x20 != 0 {
+        Operand::Imm(sign_extend(word, 5))
+    } else {
+        Operand::Reg(register(word, 0))
+    };
+
+    match word >> 12 {
+        0b0000 => Instruction::Br {
+            n: word & 0x0800 != 0,
+            z: word & 0x0400 != 0,
+            p: word & 0x0200 != 0,
+            offset: offset9,
+        },
+        0b0001 => Instruction::Add {
+            dr: r9,
+            sr1: r6,
+            src,
+        },
+        0b0010 => Instruction::Ld {
+            dr: r9,
+            offset: offset9,
+        },
+        0b0011 => Instruction::St {
+            sr: r9,
+            offset: offset9,
+        },
+        0b0100 if word & 0x0800 != 0 => Instruction::Jsr {
+            offset: sign_extend(word, 11),
+        },
+        0b0100 => Instruction::Jsrr { base: r6 },
+        0b0101 => Instruction::And {
+            dr: r9,
+            sr1: r6,
+            src,
+        },
+        0b0110 => Instruction::Ldr {
+            dr: r9,
+            base: r6,
+            offset: offset6,
+        },
+        0b0111 => Instruction::Str {
+            sr: r9,
+            base: r6,
+            offset: offset6,
+        },
+        0b1000 => Instruction::Rti,
+        0b1001 => Instruction::Not { dr: r9, sr: r6 },
+        0b1010 => Instruction::Ldi {
+            dr: r9,
+            offset: offset9,
+        },
+        0b1011 => Instruction::Sti {
+            sr: r9,
+            offset: offset9,
+        },
+        0b1100 => Instruction::Jmp { base: r6 },
+        0b1110 => Instruction::Lea {
+            dr: r9,
+            offset: offset9,
+        },
+        0b1111 => Instruction::Trap {
+            vector: (word & 0xff) as u8,
+        },
+        _ => Instruction::Data(word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::formats::{read_obj, write_obj};
+
+    fn register() -> impl Strategy<Value = Register> {
+        (0u16..8).prop_map(|r| Register::from_u16(r).unwrap())
+    }
+
+    fn operand() -> impl Strategy<Value = Operand> {
+        prop_oneof![
+            register().prop_map(Operand::Reg),
+            (-16i16..16).prop_map(Operand::Imm)
+        ]
+    }
+
+    /// Any instruction the ISA defines, with fields in range
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        let offset9 = -256i16..256;
+        let offset6 = -32i16..32;
+        prop_oneof![
+            (any::<[bool; 3]>(), offset9.clone()).prop_map(|([n, z, p], offset)| Instruction::Br {
+                n,
+                z,
+                p,
+                offset
+            }),
+            (register(), register(), operand()).prop_map(|(dr, sr1, src)| Instruction::Add {
+                dr,
+                sr1,
+                src
+            }),
+            (register(), offset9.clone()).prop_map(|(dr, offset)| Instruction::Ld { dr, offset }),
+            (register(), offset9.clone()).prop_map(|(sr, offset)| Instruction::St { sr, offset }),
+            (-1024i16..1024).prop_map(|offset| Instruction::Jsr { offset }),
+            register().prop_map(|base| Instruction::Jsrr { base }),
+            (register(), register(), operand()).prop_map(|(dr, sr1, src)| Instruction::And {
+                dr,
+                sr1,
+                src
+            }),
+            (register(), register(), offset6.clone())
+                .prop_map(|(dr, base, offset)| Instruction::Ldr { dr, base, offset }),
+            (register(), register(), offset6).prop_map(|(sr, base, offset)| Instruction::Str {
+                sr,
+                base,
+                offset
+            }),
+            Just(Instruction::Rti),
+            (register(), register()).prop_map(|(dr, sr)| Instruction::Not { dr, sr }),
+            (register(), offset9.clone()).prop_map(|(dr, offset)| Instruction::Ldi { dr, offset }),
+            (register(), offset9.clone()).prop_map(|(sr, offset)| Instruction::Sti { sr, offset }),
+            register().prop_map(|base| Instruction::Jmp { base }),
+            (register(), offset9).prop_map(|(dr, offset)| Instruction::Lea { dr, offset }),
+            any::<u8>().prop_map(|vector| Instruction::Trap { vector }),
+        ]
+    }
+
+    /// Any LC-3b instruction, with fields in range
+    fn lc3b_instruction() -> impl Strategy<Value = Lc3bInstruction> {
+        let offset6 = -32i16..32;
+        let shift = prop_oneof![Just(Shift::Lshf), Just(Shift::Rshfl), Just(Shift::Rshfa)];
+        prop_oneof![
+            (register(), register(), offset6.clone())
+                .prop_map(|(dr, base, offset)| Lc3bInstruction::Ldb { dr, base, offset }),
+            (register(), register(), offset6.clone())
+                .prop_map(|(sr, base, offset)| Lc3bInstruction::Stb { sr, base, offset }),
+            (register(), register(), offset6.clone())
+                .prop_map(|(dr, base, offset)| Lc3bInstruction::Ldw { dr, base, offset }),
+            (register(), register(), offset6).prop_map(|(sr, base, offset)| Lc3bInstruction::Stw {
+                sr,
+                base,
+                offset
+            }),
+            (register(), register(), operand()).prop_map(|(dr, sr1, src)| Lc3bInstruction::Xor {
+                dr,
+                sr1,
+                src
+            }),
+            (shift, register(), register(), 0u16..16).prop_map(|(shift, dr, sr, amount)| {
+                Lc3bInstruction::Shf {
+                    shift,
+                    dr,
+                    sr,
+                    amount,
+                }
+            }),
+            instruction().prop_filter_map("not an LC-3b instruction", |instruction| {
+                matches!(
+                    instruction,
+                    Instruction::Br { .. }
+                        | Instruction::Add { .. }
+                        | Instruction::Jsr { .. }
+                        | Instruction::Jsrr { .. }
+                        | Instruction::And { .. }
+                        | Instruction::Rti
+                        | Instruction::Jmp { .. }
+                        | Instruction::Lea { .. }
+                        | Instruction::Trap { .. }
+                )
+                .then_some(Lc3bInstruction::Lc3(instruction))
+            }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_lc3b_obj_round_trip(
+            origin in any::<u16>(),
+            stream in prop::collection::vec(lc3b_instruction(), 0..64),
+        ) {
+            let mut bin = vec![origin];
+            bin.extend(stream.iter().map(Lc3bInstruction::encode));
+            let mut obj = Vec::new();
+            write_obj(&mut obj, &bin).unwrap();
+
+            let read = read_obj(&obj[..]).unwrap();
+            prop_assert_eq!(read[0], origin);
+            let decoded: Vec<_> = read[1..].iter().map(|w| Lc3bInstruction::decode(*w)).collect();
+            prop_assert_eq!(&decoded, &stream);
+            let encoded: Vec<_> = decoded.iter().map(Lc3bInstruction::encode).collect();
+            prop_assert_eq!(&encoded[..], &bin[1..]);
+        }
+
+        #[test]
+        fn test_obj_round_trip(
+            origin in any::<u16>(),
+            stream in prop::collection::vec(instruction(), 0..64),
+        ) {
+            let mut bin = vec![origin];
+            bin.extend(stream.iter().map(Instruction::encode));
+            let mut obj = Vec::new();
+            write_obj(&mut obj, &bin).unwrap();
+
+            let read = read_obj(&obj[..]).unwrap();
+            prop_assert_eq!(read[0], origin);
+            let decoded: Vec<_> = read[1..].iter().map(|w| Instruction::decode(*w)).collect();
+            prop_assert_eq!(&decoded, &stream);
+            let encoded: Vec<_> = decoded.iter().map(Instruction::encode).collect();
+            prop_assert_eq!(&encoded[..], &bin[1..]);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let add = Instruction::Add {
+            dr: Register::R0,
+            sr1: Register::R0,
+            src: Operand::Imm(-1),
+        };
+        assert_eq!(add.encode(), 0x103f);
+
+        let br = Instruction::Br {
+            n: false,
+            z: true,
+            p: true,
+            offset: -2,
+        };
+        assert_eq!(br.encode(), 0x07fe);
+        assert_eq!(Instruction::Jmp { base: Register::R7 }.encode(), 0xc1c0);
+        assert_eq!(Instruction::Trap { vector: 0x25 }.encode(), 0xf025);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Instruction::decode(0xe002),
+            Instruction::Lea {
+                dr: Register::R0,
+                offset: 2
+            }
+        );
+        assert_eq!(Instruction::decode(0x4fff), Instruction::Jsr { offset: -1 });
+        assert_eq!(
+            Instruction::decode(0x5260),
+            Instruction::And {
+                dr: Register::R1,
+                sr1: Register::R1,
+                src: Operand::Imm(0)
+            }
+        );
+
+        // Reserved opcode and set bits in unused fields
+        assert_eq!(Instruction::decode(0xd000), Instruction::Data(0xd000));
+        assert_eq!(Instruction::decode(0x1008), Instruction::Data(0x1008));
+        assert_eq!(Instruction::decode(0xf125), Instruction::Data(0xf125));
+        assert_eq!(
+            Instruction::decode_lossy(0xf125),
+            Instruction::Trap { vector: 0x25 }
+        );
+        assert_eq!(Instruction::decode_lossy(0xd000), Instruction::Data(0xd000));
+    }
+
+    #[test]
+    fn test_decode_every_word() {
+        for word in 0..=u16::MAX {
+            assert_eq!(Instruction::decode(word).encode(), word);
+        }
+    }
+
+    #[test]
+    fn test_lc3b_decode() {
+        for word in 0..=u16::MAX {
+            assert_eq!(Lc3bInstruction::decode(word).encode(), word);
+        }
+
+        let lines: Vec<_> = [
+            0x2403, 0x7201, 0x9242, 0x967f, 0xd271, 0xe009, 0xa000, 0xd2a1,
+        ]
+        .into_iter()
+        .map(|word| Lc3bInstruction::decode(word).to_string())
+        .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "LDB R2, R0, #3",
+                "STW R1, R0, #1",
+                "XOR R1, R1, R2",
+                "NOT R3, R1",
+                "RSHFA R1, R1, #1",
+                "LEA R0, #9",
+                ".FILL xA000",
+                ".FILL xD2A1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let lines: Vec<_> = [0xe002, 0xf022, 0xf025, 0x0ffe, 0x6a41, 0xc1c0, 0xd000]
+            .into_iter()
+            .map(|word| Instruction::decode(word).to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "LEA R0, #2",
+                "PUTS",
+                "HALT",
+                "BRnzp #-2",
+                "LDR R5, R1, #1",
+                "RET",
+                ".FILL xD000"
+            ]
+        );
+        assert_eq!(Instruction::decode(0x3000).target(0x3005), Some(0x3006));
+    }
+}
@@ -0,0 +1,93 @@
+//! Assembler for the LC-3 virtual machine
+//!
+//! [`assemble`] turns source text into a [`Program`] without touching the
+//! file system. The [`Assembler`] type drives the command line tool and also
+//! writes the output files.
+
+pub mod archive;
+pub mod assembler;
+pub mod bindings;
+pub mod debug_info;
+pub mod debugger;
+pub mod disassembler;
+pub mod encoder;
+pub mod enums;
+pub mod error;
+pub mod extension;
+pub mod formats;
+pub mod formatter;
+pub mod instruction;
+pub mod linker;
+pub mod lint;
+pub mod manifest;
+pub mod map;
+pub mod object;
+pub mod options;
+pub mod program;
+pub mod simulator;
+pub mod symbols;
+pub mod target;
+pub mod testing;
+pub mod utils;
+
+pub use crate::{
+    assembler::Assembler,
+    options::AssemblerOptions,
+    program::{Diagnostic, Diagnostics, Program, Segment},
+};
+
+use crate::utils::OutputPaths;
+
+/// Assemble source text held in memory
+pub fn assemble(source: &str, options: &AssemblerOptions) -> Result<Program, Diagnostics> {
+    let mut asm = Assembler::from_source(source, OutputPaths::default(), options.clone());
+
+    match asm.build() {
+        Ok(()) => Ok(Program::from_assembler(&asm)),
+        Err(error) => Err(Diagnostics::from_assembler(&asm, error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_assemble() {
+        let source =
+            ".ORIG x3000\nLEA R0, HELLO_WORLD\nPUTS\nHALT\nHELLO_WORLD .stringz \"Hi\"\n.END\n";
+        let program = assemble(source, &AssemblerOptions::default()).unwrap();
+
+        assert_eq!(program.segments().len(), 1);
+        assert_eq!(program.segments()[0].origin, 0x3000);
+        assert_eq!(
+            program.segments()[0].words,
+            vec![0xe002, 0xf022, 0xf025, 0x48, 0x69, 0]
+        );
+        assert_eq!(program.symbol("HELLO_WORLD"), Some(0x3003));
+        assert_eq!(program.line_of(0x3001), Some(3));
+        assert_eq!(program.line_of(0x3004), Some(5));
+        assert_eq!(program.line_of(0x3006), None);
+        assert_eq!(
+            program.image(),
+            vec![0x3000, 0xe002, 0xf022, 0xf025, 0x48, 0x69, 0]
+        );
+    }
+
+    #[test]
+    fn test_assemble_diagnostics() {
+        let options = AssemblerOptions::builder().name("bad.asm").build();
+        let diagnostics =
+            assemble(".ORIG x3000\nADD R0, R1\nTRAP x04\n.END\n", &options).unwrap_err();
+
+        let diagnostic = &diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.file, "bad.asm");
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.error.kind, ErrorKind::SyntaxError);
+        assert_eq!(
+            diagnostics.to_string(),
+            "bad.asm:2: [SyntaxError] invalid syntax"
+        );
+    }
+}
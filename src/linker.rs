@@ -0,0 +1,575 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    archive::Archive,
+    error::{Error, ErrorKind, Result},
+    map::{MapSection, MapSymbol, MemoryMap},
+    object::{Object, Symbol},
+};
+
+/// Address floating sections are placed from when nothing else is given
+pub const DEFAULT_ORIGIN: u16 = 0x3000;
+
+/// Where the linker places sections, read from a TOML linker script
+///
+/// ```toml
+/// origin = 0x3000
+///
+/// [sections]
+/// "main.asm" = 0x3000
+/// "lib/print.asm:text" = 0x4000
+/// ```
+///
+/// Sections are named by the object they come from, optionally followed by
+/// `:section`. A placement overrides the `.ORIG` a section was assembled
+/// with, sections with neither are laid out from `origin` in link order.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LinkScript {
+    #[serde(default)]
+    pub origin: Option<u16>,
+    #[serde(default)]
+    pub sections: BTreeMap<String, u16>,
+}
+
+impl LinkScript {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|e| Error::with_message(ErrorKind::LinkError, e.to_string()))
+    }
+
+    /// Placement given for `section` of `object`
+    fn placement(&self, object: &str, section: &str) -> Option<u16> {
+        self.sections
+            .get(&format!("{object}:{section}"))
+            .or_else(|| self.sections.get(object))
+            .copied()
+    }
+}
+
+/// A section at its final address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub object: String,
+    pub section: String,
+    pub origin: u16,
+    pub len: u16,
+}
+
+impl Placement {
+    /// Address after the last word of the section
+    pub fn end(&self) -> u32 {
+        self.origin as u32 + self.len as u32
+    }
+}
+
+/// The loadable result of a link
+#[derive(Clone, Debug, PartialEq)]
+pub struct Linked {
+    /// The image in `.obj` layout, origin first, gaps between sections zeroed
+    pub image: Vec<u16>,
+    /// Labels at their final addresses, see [`Linker::link`] for their names
+    pub symbols: HashMap<String, u16>,
+    /// Placed sections in address order
+    pub placements: Vec<Placement>,
+    /// Placement report, symbols are referenced by the objects relocating them
+    pub map: MemoryMap,
+}
+
+/// Combines relocatable objects into one image
+///
+/// ```
+/// # use assembler::{assemble, linker::{Linker, LinkScript}, AssemblerOptions};
+/// let options = AssemblerOptions::builder().relocatable(true).build();
+/// let program = assemble("HALT\n.END\n", &options).unwrap();
+/// let mut linker = Linker::new(LinkScript::default());
+/// linker.add_object(program.object().unwrap().clone());
+/// assert_eq!(linker.link().unwrap().image, [0x3000, 0xf025]);
+/// ```
+pub struct Linker {
+    script: LinkScript,
+    objects: Vec<Object>,
+    archives: Vec<Archive>,
+}
+
+impl Linker {
+    pub fn new(script: LinkScript) -> Self {
+        Self {
+            script,
+            objects: Vec::new(),
+            archives: Vec::new(),
+        }
+    }
+
+    /// Add an object, sections are laid out in the order objects are added
+    pub fn add_object(&mut self, object: Object) {
+        self.objects.push(object);
+    }
+
+    /// Add an archive, its members are linked only to resolve imports
+    ///
+    /// Members are laid out after the objects, in the order they are pulled
+    /// in. Archives are searched in the order they are added.
+    pub fn add_archive(&mut self, archive: Archive) {
+        self.archives.push(archive);
+    }
+
+    /// Place, relocate and merge the objects
+    ///
+    /// The symbol table has every exported label and the local labels of the
+    /// objects added with [`Linker::add_object`]. A local label defined by
+    /// more than one object is named `object:LABEL`, local labels of archive
+    /// members are left out.
+    pub fn link(&self) -> Result<Linked> {
+        if self.objects.is_empty() {
+            return Err(link_error(String::from("nothing to link")));
+        }
+
+        let objects = self.select();
+        let origins = self.place(&objects)?;
+        let globals = globals(&objects, &origins)?;
+        let names = symbol_names(&objects, self.objects.len())?;
+
+        let mut symbols = HashMap::new();
+        for (i, object) in objects.iter().enumerate() {
+            for symbol in &object.symbols {
+                if let Some(name) = names[i].get(symbol.name.as_str()) {
+                    let addr = origins[i][symbol.section].wrapping_add(symbol.offset);
+                    symbols.insert(name.clone(), addr);
+                }
+            }
+        }
+
+        let mut placements = Vec::new();
+        let mut sections = Vec::new();
+        let mut references: HashMap<(usize, &str), Vec<String>> = HashMap::new();
+        let (mut code_words, mut data_words) = (0, 0);
+        for (i, object) in objects.iter().enumerate() {
+            for (j, section) in object.sections.iter().enumerate() {
+                let origin = origins[i][j];
+                let mut words = section.words.clone();
+                for relocation in &section.relocations {
+                    let site = origin.wrapping_add(relocation.offset);
+                    let (target, defined_by) = if object.imports.contains(&relocation.symbol) {
+                        *globals.get(relocation.symbol.as_str()).ok_or_else(|| {
+                            link_error(format!(
+                                "undefined symbol {} referenced by {}",
+                                relocation.symbol, object.name
+                            ))
+                        })?
+                    } else {
+                        let symbol = object.symbol(&relocation.symbol).ok_or_else(|| {
+                            link_error(format!(
+                                "{}: relocation names unknown label {}",
+                                object.name, relocation.symbol
+                            ))
+                        })?;
+                        let addr = origins[i][symbol.section].wrapping_add(symbol.offset);
+                        (addr, i)
+                    };
+                    references
+                        .entry((defined_by, relocation.symbol.as_str()))
+                        .or_default()
+                        .push(object.name.clone());
+
+                    let word = words.get_mut(relocation.offset as usize).ok_or_else(|| {
+                        link_error(format!(
+                            "{}: relocation at offset {} is outside its section",
+                            object.name, relocation.offset
+                        ))
+                    })?;
+                    match relocation.kind.offset_bits() {
+                        None => *word = target,
+                        Some(bits) => {
+                            let offset = target.wrapping_sub(site.wrapping_add(1));
+                            let limit = 1i32 << (bits - 1);
+                            let signed = offset as i16 as i32;
+                            if signed < -limit || signed >= limit {
+                                return Err(link_error(format!(
+                                    "{} x{site:04X} cannot reach {} at x{target:04X} in {}: offset {signed} does not fit in {bits} bits",
+                                    object.name,
+                                    relocation.symbol,
+                                    objects[defined_by].name
+                                )));
+                            }
+                            let mask = 0xffff >> (16 - bits);
+                            *word = (*word & !mask) | (offset & mask);
+                        }
+                    }
+                }
+
+                data_words += section.data_words;
+                code_words += section.words.len().saturating_sub(section.data_words);
+                let placement = Placement {
+                    object: object.name.clone(),
+                    section: section.name.clone(),
+                    origin,
+                    len: words.len() as u16,
+                };
+                sections.push((placement, words));
+            }
+        }
+
+        sections.sort_by_key(|(placement, _)| placement.origin);
+        for pair in sections.windows(2) {
+            let (a, b) = (&pair[0].0, &pair[1].0);
+            if a.len > 0 && b.len > 0 && a.end() > b.origin as u32 {
+                return Err(link_error(format!(
+                    "{}:{} (x{:04X}) overlaps {}:{} (x{:04X})",
+                    a.object, a.section, a.origin, b.object, b.section, b.origin
+                )));
+            }
+        }
+
+        let origin = sections[0].0.origin;
+        let mut image = vec![origin];
+        for (placement, words) in sections {
+            let start = (placement.origin - origin) as usize + 1;
+            image.resize(start.max(image.len()), 0);
+            image.extend(words);
+            placements.push(placement);
+        }
+
+        let map_sections = placements
+            .iter()
+            .map(|p| MapSection {
+                name: format!("{}:{}", p.object, p.section),
+                origin: p.origin,
+                len: p.len,
+            })
+            .collect();
+        let mut map_symbols = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            for symbol in &object.symbols {
+                let Some(name) = names[i].get(symbol.name.as_str()) else {
+                    continue;
+                };
+                map_symbols.push(MapSymbol {
+                    name: name.clone(),
+                    addr: origins[i][symbol.section].wrapping_add(symbol.offset),
+                    defined_in: object.name.clone(),
+                    referenced_by: references
+                        .remove(&(i, symbol.name.as_str()))
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        let map = MemoryMap::new(map_sections, map_symbols, code_words, data_words);
+
+        Ok(Linked {
+            image,
+            symbols,
+            placements,
+            map,
+        })
+    }
+
+    /// The objects followed by the archive members that resolve their imports
+    fn select(&self) -> Vec<Object> {
+        let mut objects = self.objects.clone();
+        let mut pulled = Vec::new();
+        let mut next = 0;
+        while next < objects.len() {
+            let mut imports = objects[next].imports.clone();
+            next += 1;
+            imports.retain(|name| !objects.iter().any(|o| o.exports().any(|s| &s.name == name)));
+            for name in imports {
+                let found = self.archives.iter().enumerate().find_map(|(i, archive)| {
+                    archive.member_defining(&name).map(|member| (i, member))
+                });
+                if let Some((i, member)) = found {
+                    if !pulled.contains(&(i, member)) {
+                        pulled.push((i, member));
+                        objects.push(self.archives[i].members[member].clone());
+                    }
+                }
+            }
+        }
+
+        objects
+    }
+
+    /// Origin of every section, indexed by object and section
+    fn place(&self, objects: &[Object]) -> Result<Vec<Vec<u16>>> {
+        let mut fixed = Vec::new();
+        let mut origins = Vec::new();
+        for object in objects {
+            let placed: Vec<_> = object
+                .sections
+                .iter()
+                .map(|section| {
+                    self.script
+                        .placement(&object.name, &section.name)
+                        .or(section.origin)
+                })
+                .collect();
+            for (section, origin) in object.sections.iter().zip(&placed) {
+                if let Some(origin) = origin {
+                    let end = *origin as u32 + section.words.len() as u32;
+                    if end > 0x10000 {
+                        return Err(link_error(format!(
+                            "{}:{} does not fit in memory",
+                            object.name, section.name
+                        )));
+                    }
+                    fixed.push((*origin as u32, end));
+                }
+            }
+            origins.push(placed);
+        }
+
+        let mut next = self.script.origin.unwrap_or(DEFAULT_ORIGIN) as u32;
+        let mut result = Vec::new();
+        for (object, placed) in objects.iter().zip(origins) {
+            let mut object_origins = Vec::new();
+            for (section, origin) in object.sections.iter().zip(placed) {
+                let origin = match origin {
+                    Some(origin) => origin,
+                    None => {
+                        let len = section.words.len() as u32;
+                        while let Some(&(_, end)) = fixed
+                            .iter()
+                            .find(|&&(start, end)| len > 0 && next < end && start < next + len)
+                        {
+                            next = end;
+                        }
+                        if next + len > 0x10000 {
+                            return Err(link_error(format!(
+                                "{}:{} does not fit in memory",
+                                object.name, section.name
+                            )));
+                        }
+                        let origin = next as u16;
+                        next += len;
+                        origin
+                    }
+                };
+                object_origins.push(origin);
+            }
+            result.push(object_origins);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Address and defining object of every exported symbol
+fn globals<'a>(
+    objects: &'a [Object],
+    origins: &[Vec<u16>],
+) -> Result<HashMap<&'a str, (u16, usize)>> {
+    let mut globals = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in object.exports() {
+            let addr = origins[i][symbol.section].wrapping_add(symbol.offset);
+            if let Some((_, other)) = globals.insert(symbol.name.as_str(), (addr, i)) {
+                return Err(link_error(format!(
+                    "{} is defined by both {} and {}",
+                    symbol.name, objects[other].name, object.name
+                )));
+            }
+        }
+    }
+
+    Ok(globals)
+}
+
+/// Names in the merged symbol table by object and label, see [`Linker::link`]
+///
+/// The first `added` objects were added directly, the rest are archive members.
+fn symbol_names(objects: &[Object], added: usize) -> Result<Vec<HashMap<&str, String>>> {
+    let listed = |i: usize, symbol: &Symbol| symbol.global || i < added;
+    let mut definitions: HashMap<&str, usize> = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|s| listed(i, s)) {
+            *definitions.entry(symbol.name.as_str()).or_default() += 1;
+        }
+    }
+
+    let mut defined_by = HashMap::new();
+    let mut names = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        let mut object_names = HashMap::new();
+        for symbol in object.symbols.iter().filter(|s| listed(i, s)) {
+            let name = if symbol.global || definitions[symbol.name.as_str()] == 1 {
+                symbol.name.clone()
+            } else {
+                format!("{}:{}", object.name, symbol.name)
+            };
+            if let Some(other) = defined_by.insert(name.clone(), i) {
+                return Err(link_error(format!(
+                    "{name} is defined by both {} and {}",
+                    objects[other].name, object.name
+                )));
+            }
+            object_names.insert(symbol.name.as_str(), name);
+        }
+        names.push(object_names);
+    }
+
+    Ok(names)
+}
+
+fn link_error(message: String) -> Error {
+    Error::with_message(ErrorKind::LinkError, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, AssemblerOptions};
+
+    fn object(name: &str, source: &str) -> Object {
+        let options = AssemblerOptions::builder()
+            .name(name)
+            .relocatable(true)
+            .build();
+        assemble(source, &options)
+            .unwrap()
+            .object()
+            .unwrap()
+            .clone()
+    }
+
+    fn objects() -> [Object; 2] {
+        [
+            object(
+                "main.asm",
+                ".EXTERN DOUBLE\n.EXTERN FACTOR\n\
+                 LD R1, SEVEN\nJSR DOUBLE\nHALT\nSEVEN .FILL #7\nPTR .FILL FACTOR\n.END\n",
+            ),
+            object(
+                "lib.asm",
+                ".GLOBAL DOUBLE\n.GLOBAL FACTOR\n\
+                 DOUBLE ADD R1, R1, R1\nRET\nFACTOR .FILL #2\n.END\n",
+            ),
+        ]
+    }
+
+    fn link(script: LinkScript, objects: impl IntoIterator<Item = Object>) -> Result<Linked> {
+        let mut linker = Linker::new(script);
+        for object in objects {
+            linker.add_object(object);
+        }
+        linker.link()
+    }
+
+    #[test]
+    fn test_link() {
+        let linked = link(LinkScript::default(), objects()).unwrap();
+        assert_eq!(
+            linked.image,
+            [0x3000, 0x2202, 0x4803, 0xf025, 0x0007, 0x3007, 0x1241, 0xc1c0, 0x0002]
+        );
+        assert_eq!(linked.symbols["PTR"], 0x3004);
+        assert_eq!(linked.symbols["FACTOR"], 0x3007);
+        assert_eq!(
+            linked.placements[1],
+            Placement {
+                object: String::from("lib.asm"),
+                section: String::from("text"),
+                origin: 0x3005,
+                len: 3,
+            }
+        );
+
+        // Placed apart, the gap is zero filled
+        let script = LinkScript::parse("[sections]\n\"lib.asm:text\" = 0x3100\n").unwrap();
+        let linked = link(script, objects()).unwrap();
+        assert_eq!(linked.image.len(), 0x103 + 1);
+        assert_eq!(linked.image[2], 0x48fe);
+        assert_eq!(linked.image[5], 0x3102);
+        assert_eq!(linked.image[6..0x101], [0; 0xfb]);
+        assert_eq!(
+            linked.map.to_string(),
+            "\
+Sections
+Origin  End     Size  Name
+x3000   x3004      5  main.asm:text
+x3100   x3102      3  lib.asm:text
+
+Gaps
+x3005   x30FF    251
+
+Symbols
+Addr    Name                     Defined in           Referenced by
+x3003   SEVEN                    main.asm
+x3004   PTR                      main.asm
+x3100   DOUBLE                   lib.asm              main.asm
+x3102   FACTOR                   lib.asm              main.asm
+
+Words
+Code        5
+Data        3
+Total       8
+"
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let [main, lib] = objects();
+        for (script, objects, message) in [
+            (
+                "sections = { \"lib.asm\" = 0x4000 }",
+                vec![main.clone(), lib.clone()],
+                "main.asm x3001 cannot reach DOUBLE at x4000 in lib.asm: offset 4094 does not fit in 11 bits",
+            ),
+            (
+                "",
+                vec![main.clone()],
+                "undefined symbol DOUBLE referenced by main.asm",
+            ),
+            (
+                "",
+                vec![main.clone(), lib.clone(), lib.clone()],
+                "DOUBLE is defined by both lib.asm and lib.asm",
+            ),
+            (
+                "sections = { \"main.asm\" = 0x3000, \"lib.asm\" = 0x3002 }",
+                vec![main, lib],
+                "main.asm:text (x3000) overlaps lib.asm:text (x3002)",
+            ),
+        ] {
+            let error = link(LinkScript::parse(script).unwrap(), objects).unwrap_err();
+            assert_eq!(error.to_string(), format!("[LinkError] {message}"));
+        }
+    }
+
+    #[test]
+    fn test_link_local_labels() {
+        let a = object("a.asm", "LOOP BRnzp LOOP\nONLY_A HALT\n.END\n");
+        let f = object(
+            "f.asm",
+            ".GLOBAL F\nF ADD R0, R0, #1\nLOOP BRnzp LOOP\n.END\n",
+        );
+        let linked = link(LinkScript::default(), [a, f.clone()]).unwrap();
+
+        let mut symbols: Vec<_> = linked.symbols.into_iter().collect();
+        symbols.sort();
+        assert_eq!(
+            symbols,
+            [
+                (String::from("F"), 0x3002),
+                (String::from("ONLY_A"), 0x3001),
+                (String::from("a.asm:LOOP"), 0x3000),
+                (String::from("f.asm:LOOP"), 0x3003),
+            ]
+        );
+
+        // A local label does not shadow another object's export
+        let h = object("h.asm", "F HALT\n.END\n");
+        let linked = link(LinkScript::default(), [h, f]).unwrap();
+        assert_eq!(linked.symbols["F"], 0x3001);
+        assert_eq!(linked.symbols["h.asm:F"], 0x3000);
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Optional checks run after a successful assembly
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// A label is defined but never used as an operand
+    UnusedLabel,
+    /// A branch was rewritten by relaxation and clobbers R7
+    RelaxedBranch,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintLevel {
+    #[default]
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// A lint hit in the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Lint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "unused-label",
+            Lint::RelaxedBranch => "relaxed-branch",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: [{}] {}",
+            self.file,
+            self.line,
+            self.lint.as_str(),
+            self.message
+        )
+    }
+}
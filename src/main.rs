@@ -1,18 +1,482 @@
-use clap::Parser;
-use cli::Cli;
+use std::{
+    fs,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    process::ExitCode,
+};
 
-pub mod assembler;
-pub mod cli;
-pub mod encoder;
-pub mod enums;
-pub mod error;
-pub mod utils;
+use assembler::{
+    archive::Archive,
+    bindings::write_bindings,
+    debugger::Debugger,
+    disassembler::disassemble,
+    error::Result,
+    formats::{read_obj, write_obj},
+    formatter::format_source,
+    linker::{LinkScript, Linker},
+    manifest::Manifest,
+    object::Object,
+    simulator::{Simulator, Stop},
+    symbols::{check_symbol_formats, read_sym, write_symbols},
+    target::Isa,
+    testing::{junit, TestSpec},
+    utils::{create_output, OutputPaths},
+    Assembler, Diagnostics, Program,
+};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use cli::{
+    ArchiveArgs, AssembleArgs, BuildArgs, Cli, Command, DebugArgs, DisasmArgs, FmtArgs, LinkArgs,
+    RunArgs, TestArgs,
+};
 
-fn main() {
+mod cli;
+
+fn main() -> ExitCode {
     let args = Cli::parse();
+    check_sym_formats(&args);
+
+    let ok = match &args.command {
+        Some(Command::Build(build_args)) => build(build_args, true),
+        Some(Command::Check(build_args)) => build(build_args, false),
+        Some(Command::Fmt(fmt_args)) => fmt(fmt_args),
+        Some(Command::Disasm(disasm_args)) => disasm(disasm_args),
+        Some(Command::Run(run_args)) => run(run_args),
+        Some(Command::Test(test_args)) => test(test_args),
+        Some(Command::Debug(debug_args)) => debug(debug_args),
+        Some(Command::Link(link_args)) => link(link_args),
+        Some(Command::Archive(archive_args)) => archive(archive_args),
+        None => {
+            if args.assemble.file.is_none() {
+                Cli::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--file <FILE> is required without a subcommand",
+                    )
+                    .exit();
+            }
+            assemble(&args.assemble, true)
+        }
+    };
+
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Exit with a usage error if the selected symbol formats write the same file
+fn check_sym_formats(args: &Cli) {
+    let formats = match &args.command {
+        Some(Command::Build(build_args) | Command::Check(build_args)) => {
+            &build_args.assemble.sym_format
+        }
+        Some(Command::Link(link_args)) => &link_args.sym_format,
+        Some(_) => return,
+        None => &args.assemble.sym_format,
+    };
+    if let Err(e) = check_symbol_formats(formats) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, format!("--sym-format: {e}"))
+            .exit();
+    }
+}
+
+/// Assemble the single file given with `--file`, writing outputs unless `write` is off
+fn assemble(args: &AssembleArgs, write: bool) -> bool {
+    let mut asm = Assembler::new(
+        args.file().to_path_buf(),
+        args.output_paths(),
+        args.options(),
+    );
+    let res = if write { asm.assemble() } else { asm.build() };
+    report_assembler(&asm, res)
+}
+
+/// Print warnings, then the relaxations if `res` succeeded or the diagnostics if it failed
+fn report_assembler(asm: &Assembler, res: Result<()>) -> bool {
+    for warning in asm.warnings() {
+        eprintln!("[Warn] {warning}");
+    }
+
+    match res {
+        Ok(()) => {
+            for relaxation in asm.relaxations() {
+                eprintln!("[Relax] {relaxation}");
+            }
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", Diagnostics::from_assembler(asm, e));
+            false
+        }
+    }
+}
+
+/// Assemble `--file` if given, otherwise every source of the project manifest
+fn build(args: &BuildArgs, write: bool) -> bool {
+    if args.assemble.file.is_some() {
+        return assemble(&args.assemble, write);
+    }
+
+    let manifest = match Manifest::from_path(&args.manifest) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}: {e}", args.manifest.display());
+            return false;
+        }
+    };
+
+    let target = args.target.as_deref();
+    let res = if write {
+        manifest.build(target)
+    } else {
+        manifest.check(target)
+    };
+    match res {
+        Ok(programs) => {
+            report(&programs);
+            true
+        }
+        Err(diagnostics) => {
+            eprintln!("{diagnostics}");
+            false
+        }
+    }
+}
+
+fn report(programs: &[Program]) {
+    for program in programs {
+        for warning in program.warnings() {
+            eprintln!("[Warn] {warning}");
+        }
+        for relaxation in program.relaxations() {
+            eprintln!("[Relax] {relaxation}");
+        }
+    }
+}
+
+/// Format each file in place, or only report unformatted files with `--check`
+fn fmt(args: &FmtArgs) -> bool {
+    let mut ok = true;
+    for path in &args.files {
+        if let Err(e) = fmt_file(path, args.check) {
+            eprintln!("{e}");
+            ok = false;
+        }
+    }
+    ok
+}
+
+fn fmt_file(path: &Path, check: bool) -> Result<(), String> {
+    let stdin = path == Path::new("-");
+    let name = if stdin {
+        String::from("<stdin>")
+    } else {
+        path.display().to_string()
+    };
+
+    let mut source = String::new();
+    let res = if stdin {
+        io::stdin().read_to_string(&mut source)
+    } else {
+        fs::File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+    };
+    res.map_err(|e| format!("{name}: {e}"))?;
+
+    let formatted = format_source(&name, &source).map_err(|e| e.to_string())?;
+    if check {
+        if formatted != source {
+            return Err(format!("{name}: not formatted"));
+        }
+    } else if stdin {
+        print!("{formatted}");
+    } else if formatted != source {
+        fs::write(path, formatted).map_err(|e| format!("{name}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/// Disassemble an object image, naming addresses from the symbol table if given
+fn disasm(args: &DisasmArgs) -> bool {
+    match disasm_file(args) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            false
+        }
+    }
+}
+
+fn disasm_file(args: &DisasmArgs) -> Result<()> {
+    let image = if args.file == Path::new("-") {
+        read_obj(io::stdin())?
+    } else {
+        read_obj(fs::File::open(&args.file)?)?
+    };
+    let symbols = match &args.sym {
+        Some(path) => read_sym(fs::File::open(path)?)?,
+        None => Default::default(),
+    };
+
+    let source = disassemble(&image, &symbols)?;
+    let mut w = create_output(args.outfile.as_deref().unwrap_or(Path::new("-")))?;
+    w.write_all(source.as_bytes())?;
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Exit with a usage error unless `isa` is the LC-3, the only one simulated
+fn check_simulated_isa(isa: Isa) {
+    if isa != Isa::Lc3 {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!(
+                    "--isa {}: the simulator only runs lc3 programs",
+                    isa.target().name()
+                ),
+            )
+            .exit();
+    }
+}
+
+/// Assemble a source file, or read an object image, and execute it
+fn run(args: &RunArgs) -> bool {
+    check_simulated_isa(args.source.isa);
+    let image = if args.file.extension().is_some_and(|ext| ext == "obj") {
+        match fs::File::open(&args.file)
+            .map_err(Into::into)
+            .and_then(read_obj)
+        {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("{}: {e}", args.file.display());
+                return false;
+            }
+        }
+    } else {
+        let options = args.source.builder(&args.file).build();
+        let mut asm = Assembler::new(args.file.clone(), OutputPaths::default(), options);
+        let res = asm.build();
+        if !report_assembler(&asm, res) {
+            return false;
+        }
+        asm.bin().to_vec()
+    };
+
+    match run_image(args, &image) {
+        Ok(Stop::Limit) => {
+            eprintln!(
+                "{}: stopped after {} instructions",
+                args.file.display(),
+                args.limit
+            );
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            false
+        }
+    }
+}
+
+fn run_image(args: &RunArgs, image: &[u16]) -> Result<Stop> {
+    let input: Box<dyn Read> = match &args.input {
+        Some(path) => Box::new(BufReader::new(fs::File::open(path)?)),
+        None => Box::new(io::stdin()),
+    };
+
+    let mut sim = Simulator::new(input, io::stdout().lock());
+    sim.set_limit(args.limit);
+    sim.load(image)?;
+    sim.run()
+}
+
+/// Run every spec, printing a summary and writing JUnit XML if asked
+fn test(args: &TestArgs) -> bool {
+    let mut ok = true;
+    let mut reports = Vec::new();
+    for path in &args.specs {
+        match TestSpec::from_path(path).and_then(|spec| spec.run()) {
+            Ok(report) => {
+                println!("{report}");
+                ok &= report.passed();
+                reports.push(report);
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                ok = false;
+            }
+        }
+    }
+
+    if let Some(path) = &args.junit {
+        let res = create_output(path).and_then(|mut w| {
+            w.write_all(junit(&reports).as_bytes())?;
+            w.flush()?;
+            Ok(())
+        });
+        if let Err(e) = res {
+            eprintln!("{}: {e}", path.display());
+            ok = false;
+        }
+    }
+
+    ok
+}
+
+/// Assemble a program and read debugger commands from stdin until `quit`
+fn debug(args: &DebugArgs) -> bool {
+    check_simulated_isa(args.source.isa);
+    let options = args.source.builder(&args.file).build();
+    let mut asm = Assembler::new(args.file.clone(), OutputPaths::default(), options);
+    let res = asm.build();
+    if !report_assembler(&asm, res) {
+        return false;
+    }
+
+    match debug_program(args, Program::from_assembler(&asm)) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            false
+        }
+    }
+}
+
+fn debug_program(args: &DebugArgs, program: Program) -> Result<()> {
+    let input: Box<dyn Read> = match &args.input {
+        Some(path) => Box::new(BufReader::new(fs::File::open(path)?)),
+        None => Box::new(io::empty()),
+    };
+
+    let mut debugger = Debugger::new(program, input, io::stdout())?;
+    println!("{}", debugger.status());
+    let mut line = String::new();
+    loop {
+        print!("(lc3db) ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        match line.trim() {
+            "quit" | "q" => return Ok(()),
+            command => match debugger.execute(command) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => println!("{text}"),
+                Err(e) => println!("{e}"),
+            },
+        }
+    }
+}
+
+/// Link relocatable objects, writing the image and merged symbol table
+fn link(args: &LinkArgs) -> bool {
+    match link_objects(args) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{e}");
+            false
+        }
+    }
+}
+
+fn link_objects(args: &LinkArgs) -> Result<(), String> {
+    let mut script = match &args.script {
+        Some(path) => {
+            LinkScript::from_path(path).map_err(|e| format!("{}: {e}", path.display()))?
+        }
+        None => LinkScript::default(),
+    };
+    if args.origin.is_some() {
+        script.origin = args.origin;
+    }
+    script.sections.extend(args.sections.iter().cloned());
+
+    let mut linker = Linker::new(script);
+    for path in &args.objects {
+        let res = if path.extension().is_some_and(|ext| ext == "lib") {
+            Archive::from_path(path).map(|archive| linker.add_archive(archive))
+        } else {
+            Object::from_path(path).map(|object| linker.add_object(object))
+        };
+        res.map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    let linked = linker.link().map_err(|e| e.to_string())?;
+
+    let output = match &args.outfile {
+        Some(stem) => OutputPaths::new(stem),
+        None => OutputPaths::from_input(&args.objects[0]),
+    };
+    let mut others: Vec<_> = args.emit_symbols.iter().map(|b| b.extension()).collect();
+    if args.map {
+        others.push("map");
+    }
+    output
+        .check_written(&["obj"], &others)
+        .map_err(|e| e.to_string())?;
+    let mut obj = Vec::new();
+    write_obj(&mut obj, &linked.image).map_err(|e| e.to_string())?;
+    let mut outputs = vec![("obj", obj)];
+    for format in &args.sym_format {
+        let mut sym = Vec::new();
+        write_symbols(&mut sym, *format, &linked.symbols).map_err(|e| e.to_string())?;
+        outputs.push((format.extension(), sym));
+    }
+    let name = args.objects[0]
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    for format in &args.emit_symbols {
+        let mut constants = Vec::new();
+        write_bindings(&mut constants, *format, &name, &linked.symbols)
+            .map_err(|e| e.to_string())?;
+        outputs.push((format.extension(), constants));
+    }
+    if args.map {
+        outputs.push(("map", linked.map.to_string().into_bytes()));
+    }
+    for (ext, contents) in outputs {
+        let Some(path) = output.resolve(ext) else {
+            continue;
+        };
+        create_output(&path)
+            .and_then(|mut w| {
+                w.write_all(&contents)?;
+                w.flush()?;
+                Ok(())
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Bundle relocatable objects into an archive with a symbol index
+fn archive(args: &ArchiveArgs) -> bool {
+    let res = args
+        .objects
+        .iter()
+        .map(|path| Object::from_path(path).map_err(|e| format!("{}: {e}", path.display())))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|members| Archive::new(members).map_err(|e| e.to_string()))
+        .and_then(|archive| {
+            fs::write(&args.outfile, archive.to_json())
+                .map_err(|e| format!("{}: {e}", args.outfile.display()))
+        });
 
-    let mut asm = assembler::Assembler::new(args.file, args.outfile, args.debug);
-    if let Err(e) = asm.assemble() {
-        println!("{e}");
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{e}");
+            false
+        }
     }
 }
@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{
+    assembler::Assembler,
+    error::{Error, ErrorKind, Result},
+    options::AssemblerOptions,
+    program::{Diagnostic, Diagnostics, Program},
+    utils::OutputPaths,
+};
+
+/// File name looked up by `assembler build`
+pub const MANIFEST_NAME: &str = "lc3.toml";
+
+/// A project described by `lc3.toml`
+///
+/// ```toml
+/// sources = ["src/main.asm", "src/os.asm"]
+/// out-dir = "build"
+///
+/// [options]
+/// include-paths = ["lib"]
+/// defines = { STACK = 0xfe00 }
+///
+/// [target.fpga]
+/// output-formats = ["ihex"]
+/// defines = { DEBUG = 1 }
+/// ```
+///
+/// Paths are relative to the manifest. A target's settings override
+/// `[options]`, tables such as `defines` are merged key by key.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    pub sources: Vec<PathBuf>,
+    #[serde(default = "default_out_dir")]
+    pub out_dir: PathBuf,
+    #[serde(default)]
+    pub options: Table,
+    #[serde(default, rename = "target")]
+    pub targets: BTreeMap<String, Table>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn default_out_dir() -> PathBuf {
+    PathBuf::from("build")
+}
+
+impl Manifest {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse manifest text as if read from `path`, which paths are relative to
+    pub fn parse(text: &str, path: impl Into<PathBuf>) -> Result<Self> {
+        let mut manifest: Self = toml::from_str(text)
+            .map_err(|e| Error::with_message(ErrorKind::ManifestError, e.to_string()))?;
+        manifest.path = path.into();
+        Ok(manifest)
+    }
+
+    /// Directory the manifest's paths are relative to
+    pub fn root(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// Options for `target`, or the base options when `None`
+    pub fn options(&self, target: Option<&str>) -> Result<AssemblerOptions> {
+        let mut table = self.options.clone();
+        if let Some(name) = target {
+            let overrides = self.targets.get(name).ok_or_else(|| {
+                Error::with_message(ErrorKind::ManifestError, format!("no target named {name}"))
+            })?;
+            for (key, value) in overrides {
+                match (table.get_mut(key), value) {
+                    (Some(Value::Table(base)), Value::Table(extra)) => {
+                        base.extend(extra.clone());
+                    }
+                    _ => {
+                        table.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        let mut options: AssemblerOptions = table.try_into().map_err(|e: toml::de::Error| {
+            Error::with_message(ErrorKind::ManifestError, e.to_string())
+        })?;
+        for path in options
+            .include_paths
+            .iter_mut()
+            .chain(options.symbol_files.iter_mut())
+        {
+            *path = self.root().join(&*path);
+        }
+        Ok(options)
+    }
+
+    /// Directory outputs of `target` are written to
+    pub fn out_dir(&self, target: Option<&str>) -> PathBuf {
+        let out_dir = self.root().join(&self.out_dir);
+        match target {
+            Some(name) => out_dir.join(name),
+            None => out_dir,
+        }
+    }
+
+    /// Assemble every source, writing outputs named after each source file
+    ///
+    /// All sources are assembled even when some fail, so every error is
+    /// reported at once.
+    pub fn build(&self, target: Option<&str>) -> Result<Vec<Program>, Diagnostics> {
+        self.assemble_all(target, true)
+    }
+
+    /// Assemble every source without writing any output
+    pub fn check(&self, target: Option<&str>) -> Result<Vec<Program>, Diagnostics> {
+        self.assemble_all(target, false)
+    }
+
+    fn assemble_all(&self, target: Option<&str>, write: bool) -> Result<Vec<Program>, Diagnostics> {
+        let manifest_error = |error| {
+            Diagnostics::from(vec![Diagnostic {
+                file: self.path.display().to_string(),
+                line: None,
+                error,
+            }])
+        };
+        let options = self.options(target).map_err(manifest_error)?;
+
+        let mut programs = Vec::new();
+        let mut diagnostics = Vec::new();
+        for source in &self.sources {
+            let path = self.root().join(source);
+            let stem = path.file_stem().unwrap_or_default();
+            let output = OutputPaths {
+                out_dir: Some(self.out_dir(target)),
+                ..OutputPaths::new(stem)
+            };
+
+            let mut asm = Assembler::new(path.clone(), output, options.clone());
+            let res = if write { asm.assemble() } else { asm.build() };
+            match res {
+                Ok(()) => programs.push(Program::from_assembler(&asm)),
+                Err(error) => diagnostics.extend(Diagnostics::from_assembler(&asm, error)),
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(programs)
+        } else {
+            Err(Diagnostics::from(diagnostics))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::formats::OutputFormat;
+
+    const MANIFEST: &str = r#"
+        sources = ["test.ggnm", "include.ggnm"]
+
+        [options]
+        defines = { STACK = 0xfe00 }
+
+        [target.fpga]
+        output-formats = ["ihex"]
+        defines = { DEBUG = 1 }
+    "#;
+
+    #[test]
+    fn test_manifest_options() {
+        let manifest = Manifest::parse(MANIFEST, "asm/lc3.toml").unwrap();
+        assert_eq!(
+            manifest.out_dir(Some("fpga")),
+            PathBuf::from("asm/build/fpga")
+        );
+
+        let options = manifest.options(None).unwrap();
+        assert_eq!(options.output_formats, vec![OutputFormat::Obj]);
+        assert_eq!(options.defines.len(), 1);
+
+        let options = manifest.options(Some("fpga")).unwrap();
+        assert_eq!(options.output_formats, vec![OutputFormat::Ihex]);
+        assert_eq!(options.defines.get("STACK"), Some(&0xfe00));
+        assert_eq!(options.defines.get("DEBUG"), Some(&1));
+
+        let res = manifest.options(Some("board")).map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::ManifestError));
+
+        let res = Manifest::parse("source = []", MANIFEST_NAME).map_err(|e| e.kind);
+        assert_eq!(res, Err(ErrorKind::ManifestError));
+    }
+
+    #[test]
+    fn test_manifest_build() {
+        let mut manifest = Manifest::parse(MANIFEST, "asm/lc3.toml").unwrap();
+        manifest.out_dir = env::temp_dir().join("assembler-tests").join("manifest");
+
+        let programs = manifest.build(Some("fpga")).unwrap();
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].image(), programs[1].image());
+        assert!(manifest.out_dir(Some("fpga")).join("include.ihx").is_file());
+
+        manifest
+            .sources
+            .push(PathBuf::from("fault_tests/syntax_fault-1.ggnm"));
+        manifest.sources.push(PathBuf::from("missing.ggnm"));
+        let diagnostics = manifest.build(None).unwrap_err();
+        assert_eq!(diagnostics.iter().count(), 2);
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+
+/// Placement and size report written as `<OUTFILE>.map`
+///
+/// ```text
+/// Sections
+/// Origin  End     Size  Name
+/// x3000   x3004      5  main.asm:text
+/// x3100   x3102      3  lib.asm:text
+///
+/// Gaps
+/// x3005   x30FF    251
+///
+/// Symbols
+/// Addr    Name                     Defined in           Referenced by
+/// x3003   SEVEN                    main.asm
+/// x3100   DOUBLE                   lib.asm              main.asm
+///
+/// Words
+/// Code        5
+/// Data        3
+/// Total       8
+/// ```
+///
+/// Gaps are the unused addresses between sections. An assembled image
+/// lists the files using a symbol as an operand, a linked one the objects
+/// relocating it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryMap {
+    /// Sections in address order
+    pub sections: Vec<MapSection>,
+    /// Symbols ordered by address, then name
+    pub symbols: Vec<MapSymbol>,
+    pub code_words: usize,
+    pub data_words: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapSection {
+    pub name: String,
+    pub origin: u16,
+    pub len: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapSymbol {
+    pub name: String,
+    pub addr: u16,
+    pub defined_in: String,
+    pub referenced_by: Vec<String>,
+}
+
+impl MapSection {
+    /// Address of the last word
+    pub fn end(&self) -> u16 {
+        self.origin.wrapping_add(self.len.saturating_sub(1))
+    }
+}
+
+impl MemoryMap {
+    /// Build a map, sorting the sections and symbols
+    pub fn new(
+        mut sections: Vec<MapSection>,
+        mut symbols: Vec<MapSymbol>,
+        code_words: usize,
+        data_words: usize,
+    ) -> Self {
+        sections.retain(|section| section.len > 0);
+        sections.sort_by_key(|section| section.origin);
+        symbols.sort_by(|a, b| a.addr.cmp(&b.addr).then_with(|| a.name.cmp(&b.name)));
+        for symbol in symbols.iter_mut() {
+            symbol.referenced_by.sort();
+            symbol.referenced_by.dedup();
+        }
+
+        Self {
+            sections,
+            symbols,
+            code_words,
+            data_words,
+        }
+    }
+
+    /// Unused ranges between sections, as first address and length
+    pub fn gaps(&self) -> Vec<(u16, u16)> {
+        self.sections
+            .windows(2)
+            .filter_map(|pair| {
+                let start = pair[0].origin as u32 + pair[0].len as u32;
+                let end = pair[1].origin as u32;
+                (end > start).then(|| (start as u16, (end - start) as u16))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sections")?;
+        writeln!(f, "Origin  End     Size  Name")?;
+        for section in &self.sections {
+            writeln!(
+                f,
+                "x{:04X}   x{:04X}   {:>4}  {}",
+                section.origin,
+                section.end(),
+                section.len,
+                section.name
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Gaps")?;
+        let gaps = self.gaps();
+        if gaps.is_empty() {
+            writeln!(f, "none")?;
+        }
+        for (start, len) in gaps {
+            let end = start.wrapping_add(len - 1);
+            writeln!(f, "x{start:04X}   x{end:04X}   {len:>4}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Symbols")?;
+        writeln!(
+            f,
+            "{:<7} {:<24} {:<20} Referenced by",
+            "Addr", "Name", "Defined in"
+        )?;
+        for symbol in &self.symbols {
+            let entry = format!(
+                "x{:04X}   {:<24} {:<20} {}",
+                symbol.addr,
+                symbol.name,
+                symbol.defined_in,
+                symbol.referenced_by.join(", ")
+            );
+            writeln!(f, "{}", entry.trim_end())?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Words")?;
+        writeln!(f, "Code    {:>5}", self.code_words)?;
+        writeln!(f, "Data    {:>5}", self.data_words)?;
+        writeln!(f, "Total   {:>5}", self.code_words + self.data_words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{utils::OutputPaths, Assembler, AssemblerOptions};
+
+    #[test]
+    fn test_memory_map() {
+        let mut asm = Assembler::new(
+            "asm/include.ggnm".into(),
+            OutputPaths::default(),
+            AssemblerOptions::default(),
+        );
+        asm.build().unwrap();
+        let map = asm.memory_map();
+
+        assert_eq!(
+            map.symbols,
+            [MapSymbol {
+                name: String::from("HELLO_WORLD"),
+                addr: 0x3003,
+                defined_in: String::from("asm/include/hello.inc"),
+                referenced_by: vec![String::from("asm/include.ggnm")],
+            }]
+        );
+        assert_eq!((map.code_words, map.data_words), (3, 13));
+        assert_eq!(map.sections[0].end(), 0x300f);
+        assert!(map
+            .to_string()
+            .ends_with("Code        3\nData       13\nTotal      16\n"));
+
+        let map = MemoryMap::new(
+            vec![
+                MapSection {
+                    name: String::from("b"),
+                    origin: 0x4000,
+                    len: 2,
+                },
+                MapSection {
+                    name: String::from("a"),
+                    origin: 0x3000,
+                    len: 0x10,
+                },
+            ],
+            Vec::new(),
+            0,
+            0,
+        );
+        assert_eq!(map.sections[0].name, "a");
+        assert_eq!(map.gaps(), [(0x3010, 0xff0)]);
+    }
+}
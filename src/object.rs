@@ -0,0 +1,244 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Format version written to `version`, raised on incompatible changes
+pub const OBJECT_VERSION: u32 = 1;
+
+/// A relocatable object, written as `<OUTFILE>.rel` by `--relocatable`
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "name": "lib/print.asm",
+///   "sections": [
+///     {
+///       "name": "text",
+///       "words": [18432, 12290, 49600],
+///       "relocations": [{ "offset": 0, "kind": "pc11", "symbol": "DIVIDE" }]
+///     }
+///   ],
+///   "symbols": [{ "name": "PRINT", "section": 0, "offset": 0, "global": true }],
+///   "imports": ["DIVIDE"]
+/// }
+/// ```
+///
+/// A section has an `origin` only when its source fixed one with `.ORIG`,
+/// symbol and relocation offsets count words from the start of their
+/// section. The linker overwrites the field a relocation names with the
+/// final address of its symbol: the low 9 or 11 bits with the offset from
+/// the word after the instruction, or the whole word for `abs16`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Object {
+    pub version: u32,
+    /// Source the object was assembled from, used in link errors
+    pub name: String,
+    pub sections: Vec<Section>,
+    /// Labels defined by the object, exported ones are `global`
+    pub symbols: Vec<Symbol>,
+    /// Labels declared with `.EXTERN`
+    pub imports: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Section {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<u16>,
+    pub words: Vec<u16>,
+    /// Words written by data directives, the rest are instructions
+    #[serde(default)]
+    pub data_words: usize,
+    #[serde(default)]
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Symbol {
+    pub name: String,
+    pub section: usize,
+    pub offset: u16,
+    #[serde(default)]
+    pub global: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+/// Field of a word patched by a relocation
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RelocationKind {
+    /// PC-relative 9-bit offset of BR, LD, ST, LDI, STI and LEA
+    Pc9,
+    /// PC-relative 11-bit offset of JSR
+    Pc11,
+    /// Whole word holding an address, as written by `.FILL LABEL`
+    Abs16,
+}
+
+impl RelocationKind {
+    /// Bits of the PC-relative offset, None for absolute words
+    pub fn offset_bits(&self) -> Option<u16> {
+        match self {
+            Self::Pc9 => Some(9),
+            Self::Pc11 => Some(11),
+            Self::Abs16 => None,
+        }
+    }
+}
+
+impl Object {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let object: Self = serde_json::from_str(text)
+            .map_err(|e| Error::with_message(ErrorKind::ObjectError, e.to_string()))?;
+        if object.version != OBJECT_VERSION {
+            return Err(Error::with_message(
+                ErrorKind::ObjectError,
+                format!("unsupported object version {}", object.version),
+            ));
+        }
+        Ok(object)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("objects serialize") + "\n"
+    }
+
+    /// Symbols other objects may refer to
+    pub fn exports(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|symbol| symbol.global)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, AssemblerOptions};
+
+    #[test]
+    fn test_object() {
+        let source = "\
+.GLOBAL PRINT
+.EXTERN DIVIDE
+.EXTERN TABLE
+PRINT JSR DIVIDE
+    LD R0, TABLE
+    LEA R1, DATA
+    BRnzp PRINT
+    RET
+DATA .FILL DATA
+    .FILL TABLE
+.END
+";
+        let options = AssemblerOptions::builder()
+            .name("print.asm")
+            .relocatable(true)
+            .build();
+        let object = assemble(source, &options)
+            .unwrap()
+            .object()
+            .unwrap()
+            .clone();
+
+        let [section] = &object.sections[..] else {
+            panic!("expected one section");
+        };
+        assert_eq!(section.origin, None);
+        assert_eq!(
+            section.words,
+            [0x4800, 0x2000, 0xe202, 0x0ffc, 0xc1c0, 0x0005, 0x0000]
+        );
+        let relocations: Vec<_> = section
+            .relocations
+            .iter()
+            .map(|r| (r.offset, r.kind, r.symbol.as_str()))
+            .collect();
+        assert_eq!(
+            relocations,
+            [
+                (0, RelocationKind::Pc11, "DIVIDE"),
+                (1, RelocationKind::Pc9, "TABLE"),
+                (5, RelocationKind::Abs16, "DATA"),
+                (6, RelocationKind::Abs16, "TABLE"),
+            ]
+        );
+        assert_eq!(
+            object.exports().collect::<Vec<_>>(),
+            [&Symbol {
+                name: String::from("PRINT"),
+                section: 0,
+                offset: 0,
+                global: true,
+            }]
+        );
+        assert_eq!(object.symbol("DATA").map(|s| s.offset), Some(5));
+        assert_eq!(object.imports, ["DIVIDE", "TABLE"]);
+        assert_eq!(Object::from_json(&object.to_json()).unwrap(), object);
+    }
+
+    #[test]
+    fn test_object_declarations_before_origin() {
+        let source = ".GLOBAL PRINT\n.EXTERN PUTS_IT\n.ORIG x4000\nPRINT JSR PUTS_IT\nRET\n.END\n";
+        let options = AssemblerOptions::builder().relocatable(true).build();
+        let program = assemble(source, &options).unwrap();
+        let object = program.object().unwrap();
+
+        let [section] = &object.sections[..] else {
+            panic!("expected one section");
+        };
+        assert_eq!(section.origin, Some(0x4000));
+        assert_eq!(section.words, [0x4800, 0xc1c0]);
+        assert_eq!(object.exports().count(), 1);
+        assert_eq!(object.imports, ["PUTS_IT"]);
+        assert_eq!(program.line_of(0x4000), Some(4));
+    }
+
+    #[test]
+    fn test_object_errors() {
+        let options = AssemblerOptions::builder().relocatable(true).build();
+        for (source, message) in [
+            (
+                ".GLOBAL NOPE\nHALT\n.END\n",
+                "exported label NOPE is not defined",
+            ),
+            (
+                ".EXTERN TWICE\nTWICE HALT\n.END\n",
+                "TWICE is both defined and external",
+            ),
+        ] {
+            let diagnostics = assemble(source, &options).unwrap_err();
+            let error = &diagnostics.iter().next().unwrap().error;
+            assert_eq!(error.to_string(), format!("[ObjectError] {message}"));
+        }
+
+        // Without --relocatable external labels cannot be resolved
+        let diagnostics = assemble(
+            ".ORIG x3000\n.EXTERN FAR\nJSR FAR\n.END\n",
+            &AssemblerOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            diagnostics.to_string(),
+            "<source>:3: [MissingLabelError] FAR is external, assemble with --relocatable and link"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    bindings::BindingFormat,
+    extension::{Extensions, IsaExtension},
+    formats::{Endian, OutputFormat},
+    lint::{Lint, LintLevel},
+    symbols::SymbolFormat,
+    target::Isa,
+};
+
+/// Source dialect accepted by the assembler
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dialect {
+    /// Plain `BR` encodes with no condition bits set and never branches
+    #[default]
+    Native,
+    /// Plain `BR` is `BRnzp`, as in lc3as and lc3tools
+    Lc3as,
+}
+
+/// Settings for an assembly
+///
+/// Use [`AssemblerOptions::builder`] to construct, or deserialize from a
+/// project file with kebab-case keys.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+#[non_exhaustive]
+pub struct AssemblerOptions {
+    /// Name of in-memory source in diagnostics
+    pub name: String,
+    pub dialect: Dialect,
+    /// Instruction set the source is written for
+    pub isa: Isa,
+    /// Custom instructions in the reserved opcode, registered in code
+    #[serde(skip)]
+    pub extensions: Extensions,
+    /// Directories searched by `.INCLUDE` after the including file's directory
+    pub include_paths: Vec<PathBuf>,
+    /// Symbol tables whose labels are usable as absolute addresses
+    pub symbol_files: Vec<PathBuf>,
+    /// Constants usable wherever a label is
+    pub defines: BTreeMap<String, u16>,
+    pub output_formats: Vec<OutputFormat>,
+    pub symbol_formats: Vec<SymbolFormat>,
+    /// Languages the symbol table is exported to as constants
+    pub bindings: Vec<BindingFormat>,
+    /// Byte order of raw binary output
+    pub endian: Endian,
+    pub lints: BTreeMap<Lint, LintLevel>,
+    /// Labels are case sensitive unless turned off
+    pub case_sensitive: bool,
+    /// Origin assumed when the source has no `.ORIG`
+    pub default_origin: Option<u16>,
+    /// Rewrite out-of-range BR and JSR into long-range sequences
+    pub relax: bool,
+    /// Emit a listing file
+    pub listing: bool,
+    /// Emit source locations of every word as `<OUTFILE>.dbg.json`
+    pub debug_info: bool,
+    /// Emit a relocatable object as `<OUTFILE>.rel` instead of an image
+    pub relocatable: bool,
+    /// Emit section placement, symbol references and word counts as `<OUTFILE>.map`
+    pub map: bool,
+    pub debug: bool,
+}
+
+impl Default for AssemblerOptions {
+    fn default() -> Self {
+        Self {
+            name: String::from("<source>"),
+            dialect: Dialect::default(),
+            isa: Isa::default(),
+            extensions: Extensions::default(),
+            include_paths: Vec::new(),
+            symbol_files: Vec::new(),
+            defines: BTreeMap::new(),
+            output_formats: vec![OutputFormat::Obj],
+            symbol_formats: vec![SymbolFormat::Sym],
+            bindings: Vec::new(),
+            endian: Endian::default(),
+            lints: BTreeMap::new(),
+            case_sensitive: true,
+            default_origin: None,
+            relax: false,
+            listing: false,
+            debug_info: false,
+            relocatable: false,
+            map: false,
+            debug: false,
+        }
+    }
+}
+
+impl AssemblerOptions {
+    pub fn builder() -> AssemblerOptionsBuilder {
+        AssemblerOptionsBuilder::default()
+    }
+
+    pub fn lint_level(&self, lint: Lint) -> LintLevel {
+        self.lints.get(&lint).copied().unwrap_or_default()
+    }
+}
+
+/// Builder for [`AssemblerOptions`], unset fields keep their defaults
+#[derive(Clone, Debug, Default)]
+pub struct AssemblerOptionsBuilder {
+    options: AssemblerOptions,
+}
+
+impl AssemblerOptionsBuilder {
+    /// Continue from existing options, e.g. ones read from a project file
+    pub fn from_options(options: AssemblerOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.options.name = name.into();
+        self
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.options.dialect = dialect;
+        self
+    }
+
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.options.isa = isa;
+        self
+    }
+
+    /// Add the instructions of an extension
+    ///
+    /// Assembly fails if one of its mnemonics is already an opcode, directive or operand.
+    pub fn extension(mut self, extension: impl IsaExtension + 'static) -> Self {
+        self.options.extensions.push(extension);
+        self
+    }
+
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.include_paths.push(path.into());
+        self
+    }
+
+    pub fn symbol_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.symbol_files.push(path.into());
+        self
+    }
+
+    pub fn define(mut self, name: impl Into<String>, value: u16) -> Self {
+        self.options.defines.insert(name.into(), value);
+        self
+    }
+
+    /// Replace the output formats
+    pub fn output_formats(mut self, formats: impl IntoIterator<Item = OutputFormat>) -> Self {
+        self.options.output_formats = formats.into_iter().collect();
+        self
+    }
+
+    /// Replace the symbol table formats
+    pub fn symbol_formats(mut self, formats: impl IntoIterator<Item = SymbolFormat>) -> Self {
+        self.options.symbol_formats = formats.into_iter().collect();
+        self
+    }
+
+    /// Replace the languages symbols are exported to
+    pub fn bindings(mut self, formats: impl IntoIterator<Item = BindingFormat>) -> Self {
+        self.options.bindings = formats.into_iter().collect();
+        self
+    }
+
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.options.endian = endian;
+        self
+    }
+
+    pub fn lint(mut self, lint: Lint, level: LintLevel) -> Self {
+        self.options.lints.insert(lint, level);
+        self
+    }
+
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.options.case_sensitive = case_sensitive;
+        self
+    }
+
+    pub fn default_origin(mut self, origin: u16) -> Self {
+        self.options.default_origin = Some(origin);
+        self
+    }
+
+    pub fn relax(mut self, relax: bool) -> Self {
+        self.options.relax = relax;
+        self
+    }
+
+    pub fn listing(mut self, listing: bool) -> Self {
+        self.options.listing = listing;
+        self
+    }
+
+    pub fn debug_info(mut self, debug_info: bool) -> Self {
+        self.options.debug_info = debug_info;
+        self
+    }
+
+    pub fn relocatable(mut self, relocatable: bool) -> Self {
+        self.options.relocatable = relocatable;
+        self
+    }
+
+    pub fn map(mut self, map: bool) -> Self {
+        self.options.map = map;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.options.debug = debug;
+        self
+    }
+
+    pub fn build(self) -> AssemblerOptions {
+        self.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let options = AssemblerOptions::builder()
+            .dialect(Dialect::Lc3as)
+            .include_path("lib")
+            .define("STACK", 0xfe00)
+            .output_formats([OutputFormat::Obj, OutputFormat::Hex])
+            .lint(Lint::UnusedLabel, LintLevel::Deny)
+            .case_sensitive(false)
+            .default_origin(0x3000)
+            .build();
+
+        assert_eq!(options.dialect, Dialect::Lc3as);
+        assert_eq!(options.include_paths, vec![PathBuf::from("lib")]);
+        assert_eq!(options.defines.get("STACK"), Some(&0xfe00));
+        assert_eq!(options.lint_level(Lint::UnusedLabel), LintLevel::Deny);
+        assert_eq!(options.lint_level(Lint::RelaxedBranch), LintLevel::Allow);
+        assert!(!options.case_sensitive);
+        assert_eq!(options.default_origin, Some(0x3000));
+        assert!(!options.relax);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let options: AssemblerOptions = toml::from_str(
+            r#"
+            dialect = "lc3as"
+            include-paths = ["lib"]
+            output-formats = ["obj", "ihex"]
+            default-origin = 0x3000
+            defines = { STACK = 0xfe00 }
+            lints = { unused-label = "warn" }
+            "#,
+        )
+        .unwrap();
+
+        let expected = AssemblerOptions::builder()
+            .dialect(Dialect::Lc3as)
+            .include_path("lib")
+            .output_formats([OutputFormat::Obj, OutputFormat::Ihex])
+            .default_origin(0x3000)
+            .define("STACK", 0xfe00)
+            .lint(Lint::UnusedLabel, LintLevel::Warn)
+            .build();
+        assert_eq!(options, expected);
+
+        let res: Result<AssemblerOptions, _> = toml::from_str("orgin = 0x3000");
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assembler::{Assembler, LineOrigin, Relaxation, SourceSpan},
+    error::Error,
+    lint::Warning,
+    object::Object,
+};
+
+/// Words placed contiguously from an origin
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+/// Result of a successful assembly
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    segments: Vec<Segment>,
+    sym_table: HashMap<String, u16>,
+    source_map: Vec<SourceSpan>,
+    files: Vec<PathBuf>,
+    included_from: Vec<Option<LineOrigin>>,
+    relaxations: Vec<Relaxation>,
+    warnings: Vec<Warning>,
+    object: Option<Object>,
+    word_size: u16,
+}
+
+impl Program {
+    pub fn from_assembler(asm: &Assembler) -> Self {
+        let segments = match asm.bin().split_first() {
+            Some((&origin, words)) => vec![Segment {
+                origin,
+                words: words.to_vec(),
+            }],
+            None => Vec::new(),
+        };
+
+        Self {
+            segments,
+            sym_table: asm.sym_table().clone(),
+            source_map: asm.source_map().to_vec(),
+            files: asm.files().to_vec(),
+            included_from: asm.included_from().to_vec(),
+            relaxations: asm.relaxations().to_vec(),
+            warnings: asm.warnings().to_vec(),
+            object: asm.object(),
+            word_size: asm.options().isa.target().word_size(),
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Addresses taken by each word of the target
+    pub fn word_size(&self) -> u16 {
+        self.word_size
+    }
+
+    /// Labels and their addresses
+    pub fn symbols(&self) -> &HashMap<String, u16> {
+        &self.sym_table
+    }
+
+    pub fn symbol(&self, label: &str) -> Option<u16> {
+        self.sym_table.get(label).copied()
+    }
+
+    /// Words emitted for each source line, `start` indexes into [`Program::image`]
+    pub fn source_map(&self) -> &[SourceSpan] {
+        &self.source_map
+    }
+
+    /// Source line that emitted the word at `addr`
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.span_of(addr).map(|span| span.line)
+    }
+
+    /// File and line that emitted the word at `addr`, the file may be an include
+    pub fn location_of(&self, addr: u16) -> Option<(&Path, usize)> {
+        self.span_of(addr)
+            .map(|span| (self.files[span.file].as_path(), span.line))
+    }
+
+    fn span_of(&self, addr: u16) -> Option<&SourceSpan> {
+        self.source_map.iter().skip(1).find(|span| {
+            addr >= span.addr && ((addr - span.addr) / self.word_size) < span.len as u16
+        })
+    }
+
+    /// The source file followed by the files it included
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The `.INCLUDE` line each of [`Program::files`] was included from
+    pub fn included_from(&self) -> &[Option<LineOrigin>] {
+        &self.included_from
+    }
+
+    /// Branches rewritten by relaxation
+    pub fn relaxations(&self) -> &[Relaxation] {
+        &self.relaxations
+    }
+
+    /// Lints hit during assembly
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// The relocatable object, when assembled with `relocatable` set
+    pub fn object(&self) -> Option<&Object> {
+        self.object.as_ref()
+    }
+
+    /// The image in `.obj` layout, origin first
+    pub fn image(&self) -> Vec<u16> {
+        let mut bin = Vec::new();
+        for segment in &self.segments {
+            bin.push(segment.origin);
+            bin.extend_from_slice(&segment.words);
+        }
+        bin
+    }
+}
+
+/// An error located in the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<usize>,
+    pub error: Error,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.error),
+            None => write!(f, "{}: {}", self.file, self.error),
+        }
+    }
+}
+
+/// Errors reported by a failed assembly
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn from_assembler(asm: &Assembler, error: Error) -> Self {
+        let (file, line) = match asm.error_location() {
+            Some((file, line)) => (file, Some(line)),
+            None => (asm.source_name(), None),
+        };
+        Self(vec![Diagnostic { file, line, error }])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+}
+
+impl From<Vec<Diagnostic>> for Diagnostics {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        Self(diagnostics)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<_> = self.0.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,588 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{Read, Write},
+};
+
+use crate::{
+    enums::Register,
+    error::{Error, ErrorKind, Result},
+    instruction::{Instruction, Operand},
+};
+
+/// Keyboard status register, bit 15 is set while a key is waiting
+pub const KBSR: u16 = 0xfe00;
+/// Keyboard data register, reading it takes the waiting key
+pub const KBDR: u16 = 0xfe02;
+/// Display status register, the display is always ready
+pub const DSR: u16 = 0xfe04;
+/// Display data register, writing it prints the low byte
+pub const DDR: u16 = 0xfe06;
+/// Machine control register, clearing bit 15 halts the machine
+pub const MCR: u16 = 0xfffe;
+
+/// Instructions executed before [`Simulator::run`] gives up, by default
+pub const DEFAULT_LIMIT: u64 = 10_000_000;
+
+/// Condition codes set by the last instruction that wrote a register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    N,
+    Z,
+    P,
+}
+
+impl Condition {
+    fn of(value: u16) -> Self {
+        match value as i16 {
+            0 => Self::Z,
+            v if v < 0 => Self::N,
+            _ => Self::P,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Why [`Simulator::run`] returned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// `HALT` ran or the machine control register was cleared
+    Halted,
+    /// Execution reached a breakpoint at this address
+    Breakpoint(u16),
+    /// The instruction limit was reached
+    Limit,
+}
+
+/// An LC-3 machine running in user mode
+///
+/// The standard trap routines run natively instead of through an OS image:
+/// `GETC` and `IN` read bytes from `input`, `OUT`, `PUTS`, `PUTSP` and `IN`
+/// write to `output`. Other trap vectors jump through the vector table.
+/// The keyboard and display are mapped to the same streams through their
+/// device registers.
+pub struct Simulator<R, W> {
+    memory: Vec<u16>,
+    registers: [u16; 8],
+    pc: u16,
+    condition: Condition,
+    cycles: u64,
+    limit: u64,
+    halted: bool,
+    breakpoints: BTreeSet<u16>,
+    input: R,
+    output: W,
+    /// Key read ahead to answer KBSR
+    key: Option<u8>,
+}
+
+impl<R: Read, W: Write> Simulator<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+            registers: [0; 8],
+            pc: 0x3000,
+            condition: Condition::Z,
+            cycles: 0,
+            limit: DEFAULT_LIMIT,
+            halted: false,
+            breakpoints: BTreeSet::new(),
+            input,
+            output,
+            key: None,
+        }
+    }
+
+    /// Copy an image in `.obj` layout into memory and start at its origin
+    pub fn load(&mut self, image: &[u16]) -> Result<()> {
+        let Some((&origin, words)) = image.split_first() else {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                String::from("image has no origin"),
+            ));
+        };
+
+        for (i, word) in words.iter().enumerate() {
+            self.memory[usize::from(origin.wrapping_add(i as u16))] = *word;
+        }
+        self.pc = origin;
+        self.halted = false;
+        Ok(())
+    }
+
+    /// Total number of instructions executed
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Instructions [`Simulator::run`] may execute in total
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn register(&self, r: Register) -> u16 {
+        self.registers[r as usize]
+    }
+
+    pub fn set_register(&mut self, r: Register, value: u16) {
+        self.registers[r as usize] = value;
+    }
+
+    pub fn condition(&self) -> Condition {
+        self.condition
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Memory at `addr`, without the side effects of a device register read
+    pub fn peek(&self, addr: u16) -> u16 {
+        self.memory[usize::from(addr)]
+    }
+
+    /// Store to memory without the side effects of a device register write
+    pub fn poke(&mut self, addr: u16, value: u16) {
+        self.memory[usize::from(addr)] = value;
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Stop [`Simulator::run`] before the instruction at `addr` executes
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    /// Execute instructions until the machine halts, a breakpoint or the limit
+    ///
+    /// A breakpoint at the PC run starts from does not stop it, so calling
+    /// run again continues past the breakpoint it last stopped at.
+    pub fn run(&mut self) -> Result<Stop> {
+        let stop = loop {
+            if self.halted {
+                break Stop::Halted;
+            }
+            if self.cycles >= self.limit {
+                break Stop::Limit;
+            }
+            self.step()?;
+            if !self.halted && self.breakpoints.contains(&self.pc) {
+                break Stop::Breakpoint(self.pc);
+            }
+        };
+        self.output.flush()?;
+        Ok(stop)
+    }
+
+    /// Execute the instruction at the PC, does nothing once halted
+    pub fn step(&mut self) -> Result<()> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let addr = self.pc;
+        let pc = addr.wrapping_add(1);
+        self.pc = pc;
+        self.cycles += 1;
+
+        match Instruction::decode_lossy(self.peek(addr)) {
+            Instruction::Br { n, z, p, offset } => {
+                let taken = match self.condition {
+                    Condition::N => n,
+                    Condition::Z => z,
+                    Condition::P => p,
+                };
+                if taken {
+                    self.pc = pc.wrapping_add_signed(offset);
+                }
+            }
+            Instruction::Add { dr, sr1, src } => {
+                let value = self.register(sr1).wrapping_add(self.operand(src));
+                self.set_result(dr, value);
+            }
+            Instruction::And { dr, sr1, src } => {
+                let value = self.register(sr1) & self.operand(src);
+                self.set_result(dr, value);
+            }
+            Instruction::Not { dr, sr } => self.set_result(dr, !self.register(sr)),
+            Instruction::Ld { dr, offset } => {
+                let value = self.read(pc.wrapping_add_signed(offset))?;
+                self.set_result(dr, value);
+            }
+            Instruction::Ldi { dr, offset } => {
+                let pointer = self.read(pc.wrapping_add_signed(offset))?;
+                let value = self.read(pointer)?;
+                self.set_result(dr, value);
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                let value = self.read(self.register(base).wrapping_add_signed(offset))?;
+                self.set_result(dr, value);
+            }
+            // LEA leaves the condition codes alone since the 3rd edition ISA
+            Instruction::Lea { dr, offset } => {
+                self.set_register(dr, pc.wrapping_add_signed(offset));
+            }
+            Instruction::St { sr, offset } => {
+                self.write(pc.wrapping_add_signed(offset), self.register(sr))?;
+            }
+            Instruction::Sti { sr, offset } => {
+                let pointer = self.read(pc.wrapping_add_signed(offset))?;
+                self.write(pointer, self.register(sr))?;
+            }
+            Instruction::Str { sr, base, offset } => {
+                let addr = self.register(base).wrapping_add_signed(offset);
+                self.write(addr, self.register(sr))?;
+            }
+            Instruction::Jsr { offset } => {
+                self.set_register(Register::R7, pc);
+                self.pc = pc.wrapping_add_signed(offset);
+            }
+            Instruction::Jsrr { base } => {
+                self.pc = self.register(base);
+                self.set_register(Register::R7, pc);
+            }
+            Instruction::Jmp { base } => self.pc = self.register(base),
+            Instruction::Trap { vector } => {
+                self.set_register(Register::R7, pc);
+                self.trap(vector, addr)?;
+            }
+            Instruction::Rti => {
+                return Err(fault(addr, String::from("RTI outside supervisor mode")));
+            }
+            Instruction::Data(word) => {
+                return Err(fault(addr, format!("illegal opcode in x{word:04X}")));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn operand(&self, src: Operand) -> u16 {
+        match src {
+            Operand::Reg(r) => self.register(r),
+            Operand::Imm(imm) => imm as u16,
+        }
+    }
+
+    fn set_result(&mut self, dr: Register, value: u16) {
+        self.set_register(dr, value);
+        self.condition = Condition::of(value);
+    }
+
+    /// Load through the memory map
+    fn read(&mut self, addr: u16) -> Result<u16> {
+        Ok(match addr {
+            KBSR => {
+                self.poll()?;
+                if self.key.is_some() {
+                    0x8000
+                } else {
+                    0
+                }
+            }
+            KBDR => {
+                self.poll()?;
+                self.key.take().map_or(0, u16::from)
+            }
+            DSR => 0x8000,
+            _ => self.peek(addr),
+        })
+    }
+
+    /// Store through the memory map
+    fn write(&mut self, addr: u16, value: u16) -> Result<()> {
+        match addr {
+            DDR => self.output.write_all(&[value as u8])?,
+            MCR if value & 0x8000 == 0 => self.halted = true,
+            _ => {}
+        }
+        self.poke(addr, value);
+        Ok(())
+    }
+
+    /// Read ahead one key if none is waiting
+    fn poll(&mut self) -> Result<()> {
+        if self.key.is_none() {
+            self.output.flush()?;
+            let mut buf = [0];
+            if self.input.read(&mut buf)? == 1 {
+                self.key = Some(buf[0]);
+            }
+        }
+        Ok(())
+    }
+
+    fn getc(&mut self, addr: u16) -> Result<u16> {
+        self.poll()?;
+        self.key
+            .take()
+            .map(u16::from)
+            .ok_or_else(|| fault(addr, String::from("read past the end of input")))
+    }
+
+    fn trap(&mut self, vector: u8, addr: u16) -> Result<()> {
+        let r0 = self.register(Register::R0);
+        match vector {
+            0x20 => {
+                let c = self.getc(addr)?;
+                self.set_register(Register::R0, c);
+            }
+            0x21 => self.output.write_all(&[r0 as u8])?,
+            0x22 => {
+                let bytes: Vec<_> = self.string(r0).map(|word| word as u8).collect();
+                self.output.write_all(&bytes)?;
+            }
+            0x23 => {
+                self.output.write_all(b"\nInput a character> ")?;
+                let c = self.getc(addr)?;
+                self.output.write_all(&[c as u8, b'\n'])?;
+                self.set_register(Register::R0, c);
+            }
+            0x24 => {
+                let bytes: Vec<_> = self
+                    .string(r0)
+                    .flat_map(|word| word.to_le_bytes())
+                    .take_while(|byte| *byte != 0)
+                    .collect();
+                self.output.write_all(&bytes)?;
+            }
+            0x25 => self.halted = true,
+            _ => match self.peek(u16::from(vector)) {
+                0 => return Err(fault(addr, format!("no routine for TRAP x{vector:02X}"))),
+                routine => self.pc = routine,
+            },
+        }
+        Ok(())
+    }
+
+    /// Words from `addr` up to the next zero word
+    fn string(&self, addr: u16) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX)
+            .map(move |i| self.peek(addr.wrapping_add(i)))
+            .take_while(|word| *word != 0)
+    }
+}
+
+fn fault(addr: u16, message: String) -> Error {
+    Error::with_message(ErrorKind::ExecutionError, format!("x{addr:04X}: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::{assemble, formats::read_obj, symbols::read_sym, AssemblerOptions};
+
+    fn simulator<'a>(source: &str, input: &'a [u8]) -> Simulator<&'a [u8], Vec<u8>> {
+        let program = assemble(source, &AssemblerOptions::default()).unwrap();
+        let mut sim = Simulator::new(input, Vec::new());
+        sim.load(&program.image()).unwrap();
+        sim
+    }
+
+    #[test]
+    fn test_simulator_hello() {
+        let image = read_obj(File::open("roms/test.obj").unwrap()).unwrap();
+        let mut sim = Simulator::new(&b""[..], Vec::new());
+        sim.load(&image).unwrap();
+
+        assert_eq!(sim.run().unwrap(), Stop::Halted);
+        assert_eq!(sim.output(), b"Hello, World");
+        assert_eq!(sim.cycles(), 3);
+        assert_eq!(sim.register(Register::R7), 0x3003);
+    }
+
+    /// The maze rogue's BUILD_MAZE draws from `seed`, rows of `1` walls with
+    /// the player `2` on the left, a tunnel of `0` and the door `4` on the right
+    fn rogue_maze(mut seed: u16) -> Vec<u16> {
+        let (w, h) = (32, 16);
+        let mut rand = || {
+            seed = seed.wrapping_mul(15245).wrapping_add(131) & 0x7fff;
+            seed
+        };
+        let mut maze = vec![1; w * h];
+        let mut y = (rand() % h as u16) as usize;
+        maze[y * w] = 2;
+        for x in 1..w {
+            maze[y * w + x] = 0;
+            y = (y + (rand() % 3) as usize + h - 1) % h;
+            maze[y * w + x] = 0;
+        }
+        maze[y * w + w - 1] = 4;
+        maze
+    }
+
+    #[test]
+    fn test_simulator_rogue_maze() {
+        // rogue takes RAND and DIVIDE from the standard library
+        let image = read_obj(File::open("roms/rogue.obj").unwrap()).unwrap();
+        let symbols = read_sym(File::open("roms/rogue.sym").unwrap()).unwrap();
+        for seed in [0xac34, 0x0001, 0x7fff] {
+            let mut sim = Simulator::new(&b""[..], Vec::new());
+            sim.load(&image).unwrap();
+            sim.set_limit(u64::MAX);
+            sim.poke(symbols["SEED"], seed);
+            sim.poke(0x4100, 0xf025);
+            sim.set_register(Register::R6, 0x4000);
+            sim.set_register(Register::R7, 0x4100);
+            sim.set_pc(symbols["BUILD_MAZE"]);
+            assert_eq!(sim.run().unwrap(), Stop::Halted);
+
+            let maze: Vec<_> = (0x3500..0x3700).map(|addr| sim.peek(addr)).collect();
+            let expected = rogue_maze(seed);
+            assert_eq!(maze, expected, "seed x{seed:04X}");
+            let player = expected.iter().position(|cell| *cell == 2).unwrap() as u16;
+            assert_eq!(sim.peek(symbols["PX"]), 0);
+            assert_eq!(sim.peek(symbols["PY"]), player / 32);
+            assert_eq!(sim.register(Register::R6), 0x4000);
+        }
+    }
+
+    #[test]
+    fn test_simulator_arithmetic() {
+        let source = "\
+.ORIG x3000
+    AND R0, R0, #0
+    ADD R0, R0, #-5
+    BRn NEG
+    HALT
+NEG NOT R1, R0
+    LD R2, VALUE
+    AND R3, R2, R1
+    LEA R4, VALUE
+    LDR R5, R4, #0
+    ST R1, VALUE
+    HALT
+VALUE .FILL x00FF
+.END
+";
+        let mut sim = simulator(source, b"");
+        assert_eq!(sim.run().unwrap(), Stop::Halted);
+        assert_eq!(sim.register(Register::R0), (-5i16) as u16);
+        assert_eq!(sim.register(Register::R1), 4);
+        assert_eq!(sim.register(Register::R3), 4);
+        assert_eq!(sim.register(Register::R4), 0x300b);
+        assert_eq!(sim.register(Register::R5), 0xff);
+        assert_eq!(sim.condition(), Condition::P);
+        assert_eq!(sim.peek(0x300b), 4);
+    }
+
+    #[test]
+    fn test_simulator_subroutine() {
+        let source = "\
+.ORIG x3000
+    LEA R0, DOUBLE
+    LD R1, SEVEN
+    JSRR R0
+    JSR DOUBLE
+    HALT
+DOUBLE ADD R1, R1, R1
+    RET
+SEVEN .FILL #7
+.END
+";
+        let mut sim = simulator(source, b"");
+        sim.add_breakpoint(0x3005);
+        assert_eq!(sim.run().unwrap(), Stop::Breakpoint(0x3005));
+        assert_eq!(sim.register(Register::R7), 0x3003);
+        assert_eq!(sim.run().unwrap(), Stop::Breakpoint(0x3005));
+        assert_eq!(sim.run().unwrap(), Stop::Halted);
+        assert_eq!(sim.register(Register::R1), 28);
+    }
+
+    #[test]
+    fn test_simulator_traps() {
+        let source = "\
+.ORIG x3000
+    GETC
+    OUT
+    IN
+    LEA R0, PACKED
+    PUTSP
+    HALT
+PACKED .FILL x6968
+    .FILL x0021
+.END
+";
+        let mut sim = simulator(source, b"ab");
+        assert_eq!(sim.run().unwrap(), Stop::Halted);
+        assert_eq!(sim.output(), b"a\nInput a character> b\nhi!");
+        assert_eq!(sim.register(Register::R0), 0x3006);
+
+        let mut sim = simulator(source, b"a");
+        let error = sim.run().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ExecutionError);
+        assert_eq!(
+            error.to_string(),
+            "[ExecutionError] x3002: read past the end of input"
+        );
+    }
+
+    #[test]
+    fn test_simulator_devices() {
+        let source = "\
+.ORIG x3000
+WAIT LDI R1, KBSR_ADDR
+    BRzp WAIT
+    LDI R0, KBDR_ADDR
+    STI R0, DDR_ADDR
+    AND R0, R0, #0
+    STI R0, MCR_ADDR
+    HALT
+KBSR_ADDR .FILL xFE00
+KBDR_ADDR .FILL xFE02
+DDR_ADDR .FILL xFE06
+MCR_ADDR .FILL xFFFE
+.END
+";
+        let mut sim = simulator(source, b"z");
+        assert_eq!(sim.run().unwrap(), Stop::Halted);
+        assert_eq!(sim.output(), b"z");
+        assert_eq!(sim.pc(), 0x3006);
+    }
+
+    #[test]
+    fn test_simulator_faults() {
+        let mut sim = simulator(".ORIG x3000\nLOOP BRnzp LOOP\n.END\n", b"");
+        sim.set_limit(100);
+        assert_eq!(sim.run().unwrap(), Stop::Limit);
+        assert_eq!(sim.cycles(), 100);
+
+        let mut sim = simulator(".ORIG x3000\nADD R0, R0, #1\n.FILL xD000\n.END\n", b"");
+        assert_eq!(
+            sim.run().unwrap_err().to_string(),
+            "[ExecutionError] x3001: illegal opcode in xD000"
+        );
+
+        let mut sim = simulator(".ORIG x3000\n.FILL xF026\n.END\n", b"");
+        assert_eq!(sim.run().unwrap_err().kind, ErrorKind::ExecutionError);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Read a symbol table as written by the assembler or lc3as
+///
+/// Each symbol is a `//` comment holding a label and a hex address,
+/// banner and column header lines are skipped.
+pub fn read_sym<R: Read>(r: R) -> Result<HashMap<String, u16>> {
+    let mut symbols = HashMap::new();
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let Some(entry) = line.trim().strip_prefix("//") else {
+            continue;
+        };
+
+        let fields: Vec<_> = entry.split_whitespace().collect();
+        if let [label, addr] = fields[..] {
+            if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                symbols.insert(label.to_owned(), addr);
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+/// Symbol table layouts, each written as `<OUTFILE>.<ext>`
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymbolFormat {
+    /// `//\tLABEL\t\taddr` lines (.sym)
+    Sym,
+    /// The banner and columns of lc3as, in place of the native layout (.sym)
+    Lc3as,
+    /// An array of name and address objects (.sym.json)
+    Json,
+    /// `name,address` rows under a header (.sym.csv)
+    Csv,
+}
+
+impl SymbolFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SymbolFormat::Sym | SymbolFormat::Lc3as => "sym",
+            SymbolFormat::Json => "sym.json",
+            SymbolFormat::Csv => "sym.csv",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SymbolFormat::Sym => "sym",
+            SymbolFormat::Lc3as => "lc3as",
+            SymbolFormat::Json => "json",
+            SymbolFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Reject formats that would write the same file, such as sym and lc3as
+pub fn check_symbol_formats(formats: &[SymbolFormat]) -> Result<()> {
+    for (i, format) in formats.iter().enumerate() {
+        let clash = formats[..i]
+            .iter()
+            .find(|other| *other != format && other.extension() == format.extension());
+        if let Some(other) = clash {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                format!(
+                    "{} and {} both write <OUTFILE>.{}, choose one",
+                    other.name(),
+                    format.name(),
+                    format.extension()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Labels ordered by address, then name
+pub fn sorted_symbols(symbols: &HashMap<String, u16>) -> Vec<(&str, u16)> {
+    let mut labels: Vec<_> = symbols
+        .iter()
+        .map(|(label, addr)| (label.as_str(), *addr))
+        .collect();
+    labels.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+    labels
+}
+
+/// Write a symbol table in the given format
+pub fn write_symbols<W: Write>(
+    w: &mut W,
+    format: SymbolFormat,
+    symbols: &HashMap<String, u16>,
+) -> Result<()> {
+    match format {
+        SymbolFormat::Sym => write_sym(w, symbols),
+        SymbolFormat::Lc3as => write_lc3as_sym(w, symbols),
+        SymbolFormat::Json => write_json_sym(w, symbols),
+        SymbolFormat::Csv => write_csv_sym(w, symbols),
+    }
+}
+
+/// Write a symbol table as `//\tLABEL\t\taddr` lines in address order
+pub fn write_sym<W: Write>(w: &mut W, symbols: &HashMap<String, u16>) -> Result<()> {
+    for (label, addr) in sorted_symbols(symbols) {
+        w.write_all(format!("//\t{label}\t\t{addr:x}\n").as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Write a symbol table laid out exactly like lc3as does
+pub fn write_lc3as_sym<W: Write>(w: &mut W, symbols: &HashMap<String, u16>) -> Result<()> {
+    w.write_all(b"// Symbol table\n// Scope level 0:\n")?;
+    w.write_all(b"//\tSymbol Name       Page Address\n")?;
+    w.write_all(b"//\t----------------  ------------\n")?;
+    for (label, addr) in sorted_symbols(symbols) {
+        w.write_all(format!("//\t{label:<16}  {addr:04X}\n").as_bytes())?;
+    }
+    w.write_all(b"\n")?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonSymbol<'a> {
+    name: &'a str,
+    address: u16,
+}
+
+/// Write a symbol table as a JSON array of `{"name", "address"}` objects
+pub fn write_json_sym<W: Write>(w: &mut W, symbols: &HashMap<String, u16>) -> Result<()> {
+    let entries: Vec<_> = sorted_symbols(symbols)
+        .into_iter()
+        .map(|(name, address)| JsonSymbol { name, address })
+        .collect();
+    let json = serde_json::to_string_pretty(&entries).expect("symbols serialize");
+    w.write_all(json.as_bytes())?;
+    w.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// Write a symbol table as CSV with `xADDR` addresses
+pub fn write_csv_sym<W: Write>(w: &mut W, symbols: &HashMap<String, u16>) -> Result<()> {
+    w.write_all(b"name,address\n")?;
+    for (label, addr) in sorted_symbols(symbols) {
+        w.write_all(format!("{label},x{addr:04X}\n").as_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::{assemble, options::AssemblerOptions};
+
+    #[test]
+    fn test_read_sym() {
+        let symbols = read_sym(File::open("roms/test.sym").unwrap()).unwrap();
+        assert_eq!(symbols, HashMap::from([("HELLO_WORLD".to_owned(), 0x3003)]));
+
+        let lc3as = "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\tSTART             3000\n";
+        let symbols = read_sym(lc3as.as_bytes()).unwrap();
+        assert_eq!(symbols, HashMap::from([("START".to_owned(), 0x3000)]));
+    }
+
+    #[test]
+    fn test_write_symbols() {
+        let symbols = HashMap::from([
+            ("LOOP".to_owned(), 0x3001),
+            ("START".to_owned(), 0x3000),
+            ("AGAIN".to_owned(), 0x3001),
+        ]);
+        let write = |format| {
+            let mut out = Vec::new();
+            write_symbols(&mut out, format, &symbols).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            write(SymbolFormat::Sym),
+            "//\tSTART\t\t3000\n//\tAGAIN\t\t3001\n//\tLOOP\t\t3001\n"
+        );
+        let lc3as = write(SymbolFormat::Lc3as);
+        assert_eq!(
+            lc3as,
+            "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\tSTART             3000\n//\tAGAIN             3001\n//\tLOOP              3001\n\n"
+        );
+        assert_eq!(read_sym(lc3as.as_bytes()).unwrap(), symbols);
+        assert_eq!(
+            write(SymbolFormat::Csv),
+            "name,address\nSTART,x3000\nAGAIN,x3001\nLOOP,x3001\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&write(SymbolFormat::Json)).unwrap();
+        assert_eq!(
+            json[1],
+            serde_json::json!({"name": "AGAIN", "address": 0x3001})
+        );
+    }
+
+    #[test]
+    fn test_check_symbol_formats() {
+        use SymbolFormat::*;
+
+        assert!(check_symbol_formats(&[Sym, Json, Csv, Sym]).is_ok());
+        assert_eq!(
+            check_symbol_formats(&[Json, Sym, Lc3as])
+                .unwrap_err()
+                .to_string(),
+            "[ValueError] sym and lc3as both write <OUTFILE>.sym, choose one"
+        );
+
+        let options = AssemblerOptions::builder()
+            .symbol_formats([Lc3as, Sym])
+            .build();
+        assert_eq!(
+            assemble(".ORIG x3000\nHALT\n.END\n", &options)
+                .unwrap_err()
+                .to_string(),
+            "<source>: [ValueError] lc3as and sym both write <OUTFILE>.sym, choose one"
+        );
+    }
+}
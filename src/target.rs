@@ -0,0 +1,398 @@
+use std::slice::Iter;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    encoder::{
+        encode_add_imm, encode_add_reg, encode_and_imm, encode_and_reg, encode_br, encode_getc,
+        encode_halt, encode_in, encode_jmp, encode_jsr, encode_jsrr, encode_ld, encode_ldb,
+        encode_ldi, encode_ldr, encode_ldw, encode_lea, encode_not, encode_out, encode_puts,
+        encode_putsp, encode_ret, encode_rti, encode_shf, encode_st, encode_stb, encode_sti,
+        encode_str, encode_stw, encode_xor_imm, encode_xor_reg,
+    },
+    enums::{OpCode, Register, Token},
+    error::{Error, ErrorKind, Result},
+    object::RelocationKind,
+    utils::{verify_offset, verify_pc_offset},
+};
+
+/// Instruction set selected with `--isa`
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Isa {
+    /// The word addressed LC-3
+    #[default]
+    Lc3,
+    /// The byte addressed LC-3b with LDB/STB/LDW/STW, SHF and XOR
+    Lc3b,
+}
+
+impl Isa {
+    pub fn target(&self) -> &'static dyn Target {
+        match self {
+            Isa::Lc3 => &Lc3,
+            Isa::Lc3b => &Lc3b,
+        }
+    }
+}
+
+/// An instruction set the assembler encodes for
+///
+/// Directives and labels are laid out the same way for every target, in
+/// units of [`Target::word_size`]; each instruction is handed to
+/// [`Target::encode`] with its operands.
+pub trait Target {
+    /// Name given to `--isa`
+    fn name(&self) -> &'static str;
+
+    /// Whether `op` is an instruction of this target
+    ///
+    /// Other mnemonics are read as labels, so `XOR` stays usable as a label
+    /// on the LC-3.
+    fn supports(&self, op: OpCode) -> bool;
+
+    /// Addresses spanned by one word
+    fn word_size(&self) -> u16 {
+        1
+    }
+
+    /// Whether the `1101` opcode is free for custom instructions
+    fn has_reserved_opcode(&self) -> bool {
+        false
+    }
+
+    /// Encode the instruction `op`, taking its operands from `operands`
+    fn encode(&self, op: OpCode, operands: &mut Operands) -> Result<Vec<u16>>;
+}
+
+/// Operands of the instruction being encoded
+///
+/// Labels go through the assembler's resolver, which records the
+/// relocations of a relocatable object.
+pub struct Operands<'a, 'b> {
+    tokens: &'b mut Iter<'a, Token>,
+    addr: u16,
+    word_size: u16,
+    resolve: &'b mut dyn FnMut(&str, RelocationKind) -> Result<u16>,
+}
+
+impl<'a, 'b> Operands<'a, 'b> {
+    /// Operands following the instruction at `addr`
+    pub fn new(
+        tokens: &'b mut Iter<'a, Token>,
+        addr: u16,
+        word_size: u16,
+        resolve: &'b mut dyn FnMut(&str, RelocationKind) -> Result<u16>,
+    ) -> Self {
+        Self {
+            tokens,
+            addr,
+            word_size,
+            resolve,
+        }
+    }
+
+    /// The next operand, failing at the end of the statement
+    pub fn operand(&mut self) -> Result<&'a Token> {
+        self.tokens
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof))
+    }
+
+    pub fn reg(&mut self) -> Result<Register> {
+        self.operand()?.take_reg()
+    }
+
+    pub fn label(&mut self) -> Result<String> {
+        self.operand()?.take_label()
+    }
+
+    /// A signed constant of `bits` bits, masked to the field
+    pub fn imm(&mut self, bits: u16) -> Result<u16> {
+        verify_offset(self.operand()?.take_const()?, bits)
+    }
+
+    /// An unsigned constant below `2^bits`
+    pub fn unsigned(&mut self, bits: u16) -> Result<u16> {
+        let value = self.operand()?.take_const()?;
+        if value >> bits != 0 {
+            return Err(Error::new(ErrorKind::ValueError));
+        }
+        Ok(value)
+    }
+
+    /// Words from the next instruction to `label`, for a PC-relative field of `kind`
+    pub fn pc_offset(&mut self, label: &str, kind: RelocationKind) -> Result<u16> {
+        let bits = kind.offset_bits().unwrap_or(16);
+        let target = (self.resolve)(label, kind)?;
+        let distance = target.wrapping_sub(self.addr.wrapping_add(self.word_size));
+        if distance % self.word_size != 0 {
+            return Err(Error::with_message(
+                ErrorKind::ValueError,
+                format!("{label} at x{target:04X} is not word aligned"),
+            ));
+        }
+        verify_pc_offset((distance as i16 / self.word_size as i16) as u16, bits)
+    }
+}
+
+/// The LC-3, one word per address
+pub struct Lc3;
+
+impl Target for Lc3 {
+    fn name(&self) -> &'static str {
+        "lc3"
+    }
+
+    fn has_reserved_opcode(&self) -> bool {
+        true
+    }
+
+    fn supports(&self, op: OpCode) -> bool {
+        !matches!(
+            op,
+            OpCode::Ldb
+                | OpCode::Stb
+                | OpCode::Ldw
+                | OpCode::Stw
+                | OpCode::Xor
+                | OpCode::Lshf
+                | OpCode::Rshfl
+                | OpCode::Rshfa
+        )
+    }
+
+    fn encode(&self, op: OpCode, operands: &mut Operands) -> Result<Vec<u16>> {
+        let bin = match op {
+            OpCode::Br
+            | OpCode::Brn
+            | OpCode::Brnp
+            | OpCode::Brp
+            | OpCode::Brz
+            | OpCode::Brnz
+            | OpCode::Brzp
+            | OpCode::Brnzp => encode_branch(op, operands)?,
+            OpCode::Add | OpCode::And => encode_alu(op, operands)?,
+            OpCode::Jmp => encode_jmp(operands.reg()?),
+            OpCode::Jsr => {
+                let label = operands.label()?;
+                encode_jsr(operands.pc_offset(&label, RelocationKind::Pc11)?)
+            }
+            OpCode::Jsrr => encode_jsrr(operands.reg()?),
+            OpCode::Ld | OpCode::Ldi | OpCode::Lea | OpCode::St | OpCode::Sti => {
+                let r = operands.reg()?;
+                let label = operands.label()?;
+                let offset = operands.pc_offset(&label, RelocationKind::Pc9)?;
+                match op {
+                    OpCode::Ld => encode_ld(r, offset),
+                    OpCode::Ldi => encode_ldi(r, offset),
+                    OpCode::Lea => encode_lea(r, offset),
+                    OpCode::St => encode_st(r, offset),
+                    _ => encode_sti(r, offset),
+                }
+            }
+            OpCode::Ldr => {
+                let dr = operands.reg()?;
+                let base = operands.reg()?;
+                encode_ldr(dr, base, operands.imm(6)?)
+            }
+            OpCode::Str => {
+                let sr = operands.reg()?;
+                let base = operands.reg()?;
+                encode_str(sr, base, operands.imm(6)?)
+            }
+            OpCode::Not => {
+                let dr = operands.reg()?;
+                encode_not(dr, operands.reg()?)
+            }
+            OpCode::Ret => encode_ret(),
+            OpCode::Rti => encode_rti(),
+            OpCode::Trap => encode_trap(operands)?,
+            OpCode::GetC => encode_getc(),
+            OpCode::Out => encode_out(),
+            OpCode::Puts => encode_puts(),
+            OpCode::In => encode_in(),
+            OpCode::PutsP => encode_putsp(),
+            OpCode::Halt => encode_halt(),
+            _ => return Err(Error::new(ErrorKind::ParseOpCodeError)),
+        };
+        Ok(bin)
+    }
+}
+
+/// The LC-3b, byte addressed with two addresses per word
+///
+/// PC-relative offsets, `LDW` and `STW` count words, `LDB` and `STB` bytes.
+/// `LD`, `ST`, `LDI`, `STI`, `LDR` and `STR` do not exist.
+pub struct Lc3b;
+
+impl Target for Lc3b {
+    fn name(&self) -> &'static str {
+        "lc3b"
+    }
+
+    fn supports(&self, op: OpCode) -> bool {
+        !matches!(
+            op,
+            OpCode::Ld | OpCode::St | OpCode::Ldi | OpCode::Sti | OpCode::Ldr | OpCode::Str
+        )
+    }
+
+    fn word_size(&self) -> u16 {
+        2
+    }
+
+    fn encode(&self, op: OpCode, operands: &mut Operands) -> Result<Vec<u16>> {
+        let bin = match op {
+            OpCode::Ldb | OpCode::Stb | OpCode::Ldw | OpCode::Stw => {
+                let r = operands.reg()?;
+                let base = operands.reg()?;
+                let offset = operands.imm(6)?;
+                match op {
+                    OpCode::Ldb => encode_ldb(r, base, offset),
+                    OpCode::Stb => encode_stb(r, base, offset),
+                    OpCode::Ldw => encode_ldw(r, base, offset),
+                    _ => encode_stw(r, base, offset),
+                }
+            }
+            OpCode::Xor => {
+                let dr = operands.reg()?;
+                let sr1 = operands.reg()?;
+                match operands.operand()? {
+                    Token::Reg(sr2) => encode_xor_reg(dr, sr1, *sr2),
+                    Token::Const(c) => encode_xor_imm(dr, sr1, verify_offset(*c, 5)?),
+                    _ => return Err(Error::new(ErrorKind::SyntaxError)),
+                }
+            }
+            OpCode::Lshf | OpCode::Rshfl | OpCode::Rshfa => {
+                let dr = operands.reg()?;
+                let sr = operands.reg()?;
+                encode_shf(op, dr, sr, operands.unsigned(4)?)
+            }
+            // The remaining instructions encode as on the LC-3
+            _ => Lc3.encode(op, operands)?,
+        };
+        Ok(bin)
+    }
+}
+
+fn encode_branch(op: OpCode, operands: &mut Operands) -> Result<Vec<u16>> {
+    let offset = match operands.operand()? {
+        Token::Const(c) => verify_offset(*c, 9)?,
+        Token::Label(label) => operands.pc_offset(label, RelocationKind::Pc9)?,
+        _ => return Err(Error::new(ErrorKind::SyntaxError)),
+    };
+    Ok(encode_br(op, offset))
+}
+
+fn encode_alu(op: OpCode, operands: &mut Operands) -> Result<Vec<u16>> {
+    let dr = operands.reg()?;
+    let sr1 = operands.reg()?;
+    let bin = match (op, operands.operand()?) {
+        (OpCode::Add, Token::Reg(sr2)) => encode_add_reg(dr, sr1, *sr2),
+        (OpCode::Add, Token::Const(c)) => encode_add_imm(dr, sr1, verify_offset(*c, 5)?),
+        (_, Token::Reg(sr2)) => encode_and_reg(dr, sr1, *sr2),
+        (_, Token::Const(c)) => encode_and_imm(dr, sr1, verify_offset(*c, 5)?),
+        _ => return Err(Error::new(ErrorKind::SyntaxError)),
+    };
+    Ok(bin)
+}
+
+/// `TRAP` takes one of the vectors the trap routines are known by
+fn encode_trap(operands: &mut Operands) -> Result<Vec<u16>> {
+    let bin = match operands.operand()?.take_const()? {
+        0x20 => encode_getc(),
+        0x21 => encode_out(),
+        0x22 => encode_puts(),
+        0x23 => encode_in(),
+        0x24 => encode_putsp(),
+        0x25 => encode_halt(),
+        _ => return Err(Error::new(ErrorKind::SyntaxError)),
+    };
+    Ok(bin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, AssemblerOptions};
+
+    #[test]
+    fn test_lc3b() {
+        let source = "\
+.ORIG x3000
+        LEA R0, DATA
+LOOP    LDW R1, R0, #0
+        LDB R2, R0, #3
+        XOR R1, R1, R2
+        LSHF R1, R1, #2
+        RSHFA R1, R1, #1
+        NOT R3, R1
+        STW R1, R0, #1
+        BRnzp LOOP
+        HALT
+DATA    .FILL x1234
+        .BLKW #1
+END     .STRINGZ \"a\"
+.END
+";
+        let options = AssemblerOptions::builder().isa(Isa::Lc3b).build();
+        let program = assemble(source, &options).unwrap();
+        assert_eq!(
+            program.image(),
+            [
+                0x3000, 0xe009, 0x6200, 0x2403, 0x9242, 0xd242, 0xd271, 0x967f, 0x7201, 0x0ff8,
+                0xf025, 0x1234, 0, 0x61, 0
+            ]
+        );
+        assert_eq!(program.symbol("LOOP"), Some(0x3002));
+        assert_eq!(program.symbol("DATA"), Some(0x3014));
+        assert_eq!(program.symbol("END"), Some(0x3018));
+
+        let error = assemble(".ORIG x3000\nLD R0, X\nX .FILL 0\n.END\n", &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "<source>:2: [ParseOpCodeError] LD is not an instruction of the lc3b target"
+        );
+        let error = assemble(".ORIG x3001\nHALT\n.END\n", &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "<source>:1: [ValueError] origin x3001 is not word aligned"
+        );
+
+        let error = assemble(".ORIG xFF00\n.BLKW #200\n.END\n", &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "<source>:2: [ValueError] 200 words at xFF00 run past the end of memory"
+        );
+        let error = assemble(".ORIG xFFF0\nHALT\n.STRINGZ \"too long\"\n.END\n", &options);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "<source>:3: [ValueError] 9 words at xFFF2 run past the end of memory"
+        );
+    }
+
+    #[test]
+    fn test_lc3_mnemonics_as_labels() {
+        let source = ".ORIG x3000\nLD R0, XOR\nXOR .FILL #1\n.END\n";
+        let program = assemble(source, &AssemblerOptions::default()).unwrap();
+        assert_eq!(program.image(), [0x3000, 0x2000, 1]);
+        assert_eq!(program.symbol("XOR"), Some(0x3001));
+
+        let source = ".ORIG x3000\nJSR ldb\nJSR Xor\nHALT\nldb RET\nXor RET\n.END\n";
+        let program = assemble(source, &AssemblerOptions::default()).unwrap();
+        assert_eq!(
+            program.image(),
+            [0x3000, 0x4802, 0x4802, 0xf025, 0xc1c0, 0xc1c0]
+        );
+        assert_eq!(program.symbol("ldb"), Some(0x3003));
+        assert_eq!(program.symbol("Xor"), Some(0x3004));
+
+        let error = assemble(".ORIG x3000\nXOR R0, R0, R0\n.END\n", &Default::default());
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "<source>:2: [ParseOpCodeError] XOR is not an instruction of the lc3 target"
+        );
+    }
+}
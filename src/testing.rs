@@ -0,0 +1,478 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    enums::{Parseable, Register},
+    error::{Error, ErrorKind, Result},
+    formats::read_obj,
+    simulator::{Simulator, Stop, DEFAULT_LIMIT},
+    symbols::read_sym,
+    utils::parse_constant,
+};
+
+/// Address a called subroutine returns to, execution stops before running it
+pub const RETURN_ADDR: u16 = 0xfdff;
+
+/// Test cases for an object image, written in TOML
+///
+/// ```toml
+/// object = "double.obj"
+///
+/// [[test]]
+/// name = "doubles seven"
+/// call = "DOUBLE"
+/// registers = { R1 = 7 }
+/// memory = { BUFFER = [1, 2, 3] }
+/// expect = { registers = { R1 = 14 }, max-cycles = 10 }
+///
+/// [[test]]
+/// name = "greets"
+/// input = "Ada\n"
+/// expect = { output = "Hello, Ada\n" }
+/// ```
+///
+/// Paths are relative to the spec, `symbols` defaults to the object with a
+/// `.sym` extension. A case with `call` sets R7 to [`RETURN_ADDR`], jumps to
+/// the subroutine and stops when it returns, otherwise the program runs
+/// from its origin until `HALT`. Registers, addresses and values may be
+/// numbers, constants such as `"x3000"` or labels.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TestSpec {
+    /// Suite name in reports, defaults to the spec's file name
+    pub name: Option<String>,
+    pub object: PathBuf,
+    pub symbols: Option<PathBuf>,
+    /// Instruction limit of each case without its own
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(rename = "test")]
+    pub tests: Vec<TestCase>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn default_limit() -> u64 {
+    DEFAULT_LIMIT
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    /// Subroutine to call, a label or an address
+    pub call: Option<String>,
+    /// Bytes read by `GETC`, `IN` and the keyboard
+    #[serde(default)]
+    pub input: String,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Word>,
+    /// Words stored from each address before the run
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    pub limit: Option<u64>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+/// State checked once a case finishes
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Expect {
+    #[serde(default)]
+    pub registers: BTreeMap<String, Word>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Words>,
+    pub output: Option<String>,
+    pub cycles: Option<u64>,
+    pub max_cycles: Option<u64>,
+}
+
+/// A number, or a constant or label written as a string
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Word {
+    Number(i64),
+    Name(String),
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Words {
+    One(Word),
+    Many(Vec<Word>),
+}
+
+impl Word {
+    fn resolve(&self, symbols: &HashMap<String, u16>) -> Result<u16> {
+        match self {
+            Self::Number(n) => i16::try_from(*n)
+                .map(|n| n as u16)
+                .or_else(|_| u16::try_from(*n))
+                .map_err(|_| spec_error(format!("{n} does not fit in a word"))),
+            Self::Name(name) => resolve_name(name, symbols),
+        }
+    }
+}
+
+impl Words {
+    fn as_slice(&self) -> &[Word] {
+        match self {
+            Self::One(word) => std::slice::from_ref(word),
+            Self::Many(words) => words,
+        }
+    }
+}
+
+fn resolve_name(name: &str, symbols: &HashMap<String, u16>) -> Result<u16> {
+    match symbols.get(name) {
+        Some(addr) => Ok(*addr),
+        None => parse_constant(name)
+            .map_err(|_| spec_error(format!("{name} is neither a label nor a constant"))),
+    }
+}
+
+fn parse_register(name: &str) -> Result<Register> {
+    Register::parse(&name.to_uppercase())
+        .map_err(|_| spec_error(format!("{name} is not a register")))
+}
+
+fn spec_error(message: String) -> Error {
+    Error::with_message(ErrorKind::TestSpecError, message)
+}
+
+impl TestSpec {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse spec text as if read from `path`, which paths are relative to
+    pub fn parse(text: &str, path: impl Into<PathBuf>) -> Result<Self> {
+        let mut spec: Self = toml::from_str(text).map_err(|e| spec_error(e.to_string()))?;
+        spec.path = path.into();
+        Ok(spec)
+    }
+
+    /// Name of the suite in reports
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    /// Load the object image and its symbols, then run every case
+    pub fn run(&self) -> Result<TestReport> {
+        let root = self.path.parent().unwrap_or(Path::new(""));
+        let object = root.join(&self.object);
+        let image = read_obj(fs::File::open(&object)?)?;
+
+        let sym_path = match &self.symbols {
+            Some(path) => root.join(path),
+            None => object.with_extension("sym"),
+        };
+        let symbols = if self.symbols.is_some() || sym_path.exists() {
+            read_sym(fs::File::open(sym_path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(self.run_image(&image, &symbols))
+    }
+
+    /// Run every case against an image in `.obj` layout
+    pub fn run_image(&self, image: &[u16], symbols: &HashMap<String, u16>) -> TestReport {
+        TestReport {
+            name: self.name(),
+            results: self
+                .tests
+                .iter()
+                .map(|test| test.run(image, symbols, test.limit.unwrap_or(self.limit)))
+                .collect(),
+        }
+    }
+}
+
+impl TestCase {
+    /// Run the case on a fresh machine, faults count as failures
+    pub fn run(&self, image: &[u16], symbols: &HashMap<String, u16>, limit: u64) -> TestResult {
+        let mut sim = Simulator::new(self.input.as_bytes(), Vec::new());
+        sim.set_limit(limit);
+        let failures = self
+            .execute(&mut sim, image, symbols)
+            .unwrap_or_else(|e| vec![e.to_string()]);
+
+        TestResult {
+            name: self.name.clone(),
+            failures,
+            cycles: sim.cycles(),
+            output: String::from_utf8_lossy(sim.output()).into_owned(),
+        }
+    }
+
+    fn execute(
+        &self,
+        sim: &mut Simulator<&[u8], Vec<u8>>,
+        image: &[u16],
+        symbols: &HashMap<String, u16>,
+    ) -> Result<Vec<String>> {
+        sim.load(image)?;
+        for (name, value) in &self.registers {
+            sim.set_register(parse_register(name)?, value.resolve(symbols)?);
+        }
+        for (addr, words) in &self.memory {
+            let addr = resolve_name(addr, symbols)?;
+            for (i, word) in words.as_slice().iter().enumerate() {
+                sim.poke(addr.wrapping_add(i as u16), word.resolve(symbols)?);
+            }
+        }
+        if let Some(call) = &self.call {
+            sim.set_pc(resolve_name(call, symbols)?);
+            sim.set_register(Register::R7, RETURN_ADDR);
+            sim.add_breakpoint(RETURN_ADDR);
+        }
+
+        let mut failures = Vec::new();
+        match (sim.run()?, &self.call) {
+            (Stop::Limit, _) => {
+                failures.push(format!(
+                    "did not finish within {} instructions",
+                    sim.cycles()
+                ));
+                return Ok(failures);
+            }
+            (Stop::Halted, Some(call)) => failures.push(format!("halted inside {call}")),
+            _ => {}
+        }
+
+        let expect = &self.expect;
+        for (name, value) in &expect.registers {
+            let (expected, actual) = (value.resolve(symbols)?, sim.register(parse_register(name)?));
+            if expected != actual {
+                failures.push(format!(
+                    "{name}: expected x{expected:04X}, got x{actual:04X}"
+                ));
+            }
+        }
+        for (name, words) in &expect.memory {
+            let addr = resolve_name(name, symbols)?;
+            for (i, word) in words.as_slice().iter().enumerate() {
+                let addr = addr.wrapping_add(i as u16);
+                let (expected, actual) = (word.resolve(symbols)?, sim.peek(addr));
+                if expected != actual {
+                    failures.push(format!(
+                        "x{addr:04X} ({name}+{i}): expected x{expected:04X}, got x{actual:04X}"
+                    ));
+                }
+            }
+        }
+        if let Some(expected) = &expect.output {
+            let actual = String::from_utf8_lossy(sim.output());
+            if *expected != actual {
+                failures.push(format!("output: expected {expected:?}, got {actual:?}"));
+            }
+        }
+        if let Some(expected) = expect.cycles {
+            if expected != sim.cycles() {
+                failures.push(format!("cycles: expected {expected}, got {}", sim.cycles()));
+            }
+        }
+        if let Some(max) = expect.max_cycles {
+            if sim.cycles() > max {
+                failures.push(format!(
+                    "cycles: expected at most {max}, got {}",
+                    sim.cycles()
+                ));
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+/// Outcome of one case
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// Each failed assertion or the fault that ended the run
+    pub failures: Vec<String>,
+    pub cycles: u64,
+    pub output: String,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Outcome of every case in a spec
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestReport {
+    pub name: String,
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(TestResult::passed)
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed()).count()
+    }
+}
+
+/// Human summary, one line per case followed by its failures
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        for result in &self.results {
+            if result.passed() {
+                writeln!(f, "  ok     {} ({} cycles)", result.name, result.cycles)?;
+            } else {
+                writeln!(f, "  FAILED {}", result.name)?;
+                for failure in &result.failures {
+                    writeln!(f, "           {failure}")?;
+                }
+            }
+        }
+        let failed = self.failed_count();
+        write!(f, "{} passed, {failed} failed", self.results.len() - failed)
+    }
+}
+
+/// JUnit XML for CI, a `<testsuite>` per report
+pub fn junit(reports: &[TestReport]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for report in reports {
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            escape(&report.name),
+            report.results.len(),
+            report.failed_count()
+        );
+        for result in &report.results {
+            let name = escape(&result.name);
+            let classname = escape(&report.name);
+            match result.failures.first() {
+                None => {
+                    xml += &format!("    <testcase name=\"{name}\" classname=\"{classname}\"/>\n")
+                }
+                Some(first) => {
+                    xml += &format!(
+                        "    <testcase name=\"{name}\" classname=\"{classname}\">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                        escape(first),
+                        escape(&result.failures.join("\n"))
+                    );
+                }
+            }
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, AssemblerOptions};
+
+    const SOURCE: &str = "\
+.ORIG x3000
+    LD R1, SEVEN
+    JSR DOUBLE
+    ST R1, RESULT
+    LEA R0, DONE
+    PUTS
+    HALT
+DOUBLE ADD R1, R1, R1
+    RET
+SEVEN .FILL #7
+RESULT .BLKW #1
+DONE .STRINGZ \"done\"
+.END
+";
+
+    const SPEC: &str = r#"
+name = "double"
+object = "double.obj"
+
+[[test]]
+name = "doubles"
+call = "DOUBLE"
+registers = { R1 = -3 }
+expect = { registers = { R1 = -6 }, cycles = 2 }
+
+[[test]]
+name = "whole program"
+expect = { memory = { RESULT = 14 }, output = "done", max-cycles = 10 }
+
+[[test]]
+name = "wrong"
+call = "DOUBLE"
+memory = { RESULT = [1, "x0002"] }
+expect = { registers = { r1 = 1 }, memory = { RESULT = [1, 3] }, output = "hi" }
+
+[[test]]
+name = "loops"
+call = "x3000"
+limit = 5
+"#;
+
+    #[test]
+    fn test_spec() {
+        let spec = TestSpec::parse(SPEC, "tests/double.toml").unwrap();
+        assert_eq!(spec.name(), "double");
+        assert_eq!(spec.tests.len(), 4);
+        assert_eq!(spec.tests[0].registers["R1"], Word::Number(-3));
+
+        let error = TestSpec::parse("object = 1", "bad.toml").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TestSpecError);
+    }
+
+    #[test]
+    fn test_run() {
+        let program = assemble(SOURCE, &AssemblerOptions::default()).unwrap();
+        let spec = TestSpec::parse(SPEC, "tests/double.toml").unwrap();
+        let report = spec.run_image(&program.image(), program.symbols());
+
+        let [doubles, whole, wrong, loops] = &report.results[..] else {
+            panic!("expected four results");
+        };
+        assert!(doubles.passed(), "{:?}", doubles.failures);
+        assert!(whole.passed(), "{:?}", whole.failures);
+        assert_eq!(whole.output, "done");
+        assert_eq!(
+            wrong.failures,
+            [
+                "r1: expected x0001, got x0000",
+                "x300A (RESULT+1): expected x0003, got x0002",
+                "output: expected \"hi\", got \"\"",
+            ]
+        );
+        assert_eq!(loops.failures, ["did not finish within 5 instructions"]);
+        assert!(!report.passed());
+        assert!(report.to_string().ends_with("2 passed, 2 failed"));
+
+        let xml = junit(&[report]);
+        assert!(xml.contains("<testsuite name=\"double\" tests=\"4\" failures=\"2\">"));
+        assert!(xml.contains("<testcase name=\"doubles\" classname=\"double\"/>"));
+        assert!(xml.contains("<failure message=\"r1: expected x0001, got x0000\">"));
+        assert!(xml.contains("output: expected &quot;hi&quot;, got &quot;&quot;</failure>"));
+    }
+}
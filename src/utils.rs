@@ -1,4 +1,9 @@
-use std::{env, path::PathBuf};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 use crate::{
     enums::{Parseable, Token},
@@ -7,33 +12,38 @@ use crate::{
 
 /// Parse a string into a vector of tokens
 pub fn tokenize(s: &str) -> Result<Option<Vec<Token>>> {
-    let s = s.trim();
+    tokenize_with(s, &|_| false)
+}
 
-    // Empty lines and comments are treated as blanks
-    if s.is_empty() || s.starts_with(";") {
+/// Like [`tokenize`], reading words that would be labels as custom
+/// instructions where `is_custom` holds
+pub fn tokenize_with(s: &str, is_custom: &dyn Fn(&str) -> bool) -> Result<Option<Vec<Token>>> {
+    // Ignore comments at the end (if present), empty lines and comments are treated as blanks
+    let s = split_comment(s).0.trim();
+    if s.is_empty() {
         return Ok(None);
     }
 
-    // Ignore comments at the end (if present)
-    let s = s.split(';').next().unwrap().trim();
-
     let mut tokens: Vec<Token> = Vec::new();
     let mut split = s.splitn(2, char::is_whitespace);
 
     if let Some(word) = split.next() {
-        let token = Token::parse(word)?;
+        let token = match Token::parse(word)? {
+            Token::Label(_) if is_custom(word) => Token::Custom(word.to_uppercase()),
+            token => token,
+        };
         tokens.push(token.clone());
 
         if let Some(rest) = split.next() {
             match token {
-                Token::Op(_) => {
+                Token::Op(_) | Token::Custom(_) => {
                     for arg in rest.split(',') {
                         tokens.push(Token::parse(arg)?)
                     }
                 }
                 Token::Dir(_) => tokens.push(Token::parse(rest)?),
                 Token::Label(_) => {
-                    if let Some(mut rest_tokens) = tokenize(rest)? {
+                    if let Some(mut rest_tokens) = tokenize_with(rest, is_custom)? {
                         tokens.append(&mut rest_tokens);
                     }
                 }
@@ -47,6 +57,45 @@ pub fn tokenize(s: &str) -> Result<Option<Vec<Token>>> {
     }
 }
 
+/// Split at the first `;` outside a string literal
+pub fn split_comment(text: &str) -> (&str, Option<&str>) {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return (&text[..i], Some(&text[i..])),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+/// Split raw source into lines, reporting the first line that is not valid UTF-8
+pub fn decode_lines(bytes: &[u8], name: &str) -> Result<Vec<String>> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    bytes
+        .split(|&b| b == b'\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            String::from_utf8(line.to_vec()).map_err(|e| {
+                Error::with_message(
+                    ErrorKind::EncodingError,
+                    format!(
+                        "invalid UTF-8 in {name} on line {} (byte {})",
+                        i + 1,
+                        e.utf8_error().valid_up_to() + 1
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
 /// Parse constants based on prefix
 pub fn parse_constant(s: &str) -> Result<u16> {
     let res = if let Some(s) = s.strip_prefix("x") {
@@ -78,18 +127,128 @@ pub fn verify_offset(mut offset: u16, bit_count: u16) -> Result<u16> {
     Ok(result & (0xffff >> (16 - bit_count)))
 }
 
-///  Resolve directory dirname
-pub fn resolve_dir() -> PathBuf {
-    if cfg!(test) {
-        let tmpdir = env::var("ASSEMBLER_TMP_DIR").unwrap_or(String::from("./tmp"));
-        PathBuf::from(tmpdir)
-    } else if let Ok(dirname) = env::current_dir() {
-        dirname
-    } else {
-        PathBuf::from("")
+/// Validate a PC-relative offset as a signed value of bit_count bits
+pub fn verify_pc_offset(offset: u16, bit_count: u16) -> Result<u16> {
+    let limit = 1i32 << (bit_count - 1);
+    let signed = offset as i16 as i32;
+    if signed < -limit || signed >= limit {
+        return Err(Error::new(ErrorKind::ValueError));
+    }
+
+    Ok(offset & (0xffff >> (16 - bit_count)))
+}
+
+/// Where the assembler writes its output files
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OutputPaths {
+    /// Directory for outputs named after the stem, defaults to the current directory
+    pub out_dir: Option<PathBuf>,
+    /// Output path without extension, `-` writes the first image format to stdout
+    pub stem: PathBuf,
+    /// Explicit path of the object image, overrides the stem
+    pub obj: Option<PathBuf>,
+    /// Explicit path of the symbol table, overrides the stem
+    pub sym: Option<PathBuf>,
+}
+
+impl OutputPaths {
+    pub fn new(stem: impl Into<PathBuf>) -> Self {
+        Self {
+            stem: stem.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Name outputs after the input file, `asm/foo.asm` becomes `foo`
+    ///
+    /// Source read from stdin (`-`) falls back to `out`.
+    pub fn from_input(file: &Path) -> Self {
+        let stem = match file.file_stem() {
+            Some(stem) if file != Path::new("-") => stem,
+            _ => OsStr::new("out"),
+        };
+        Self::new(stem)
+    }
+
+    /// Destination of the output with the given extension, None if it is not written
+    ///
+    /// A destination of `-` means stdout.
+    pub fn resolve(&self, ext: &str) -> Option<PathBuf> {
+        let explicit = match ext {
+            "obj" => self.obj.as_ref(),
+            "sym" => self.sym.as_ref(),
+            _ => None,
+        };
+        if let Some(path) = explicit {
+            return Some(path.to_owned());
+        }
+
+        if self.stem == Path::new("-") {
+            return (ext == "obj").then(|| PathBuf::from("-"));
+        }
+
+        let mut path = match &self.out_dir {
+            Some(dir) => dir.join(&self.stem).into_os_string(),
+            None => self.stem.clone().into_os_string(),
+        };
+        path.push(format!(".{ext}"));
+        Some(PathBuf::from(path))
+    }
+
+    /// Destination of the image written as `ext`, `first` if it is the first format selected
+    ///
+    /// With a stem of `-` the first format goes to stdout and the others are
+    /// not written, unless the object image has an explicit path.
+    pub fn resolve_image(&self, ext: &str, first: bool) -> Option<PathBuf> {
+        if let Some(obj) = self.obj.as_ref().filter(|_| ext == "obj") {
+            return Some(obj.to_owned());
+        }
+        if self.stem == Path::new("-") {
+            return first.then(|| PathBuf::from("-"));
+        }
+        self.resolve(ext)
+    }
+
+    /// Fail if any of the requested outputs would not be written
+    ///
+    /// `images` are the image formats in the order selected, `others` the
+    /// extensions of the other outputs. Symbol tables are left out: with a
+    /// stem of `-` they are only written to an explicit path.
+    pub fn check_written(&self, images: &[&str], others: &[&str]) -> Result<()> {
+        let images = images
+            .iter()
+            .enumerate()
+            .filter(|(i, ext)| self.resolve_image(ext, *i == 0).is_none())
+            .map(|(_, ext)| ext);
+        let others = others.iter().filter(|ext| self.resolve(ext).is_none());
+        let dropped: Vec<_> = images.chain(others).map(|ext| format!(".{ext}")).collect();
+        if dropped.is_empty() {
+            return Ok(());
+        }
+        Err(Error::with_message(
+            ErrorKind::ValueError,
+            format!(
+                "only one output can go to stdout, {} would not be written",
+                dropped.join(", ")
+            ),
+        ))
     }
 }
 
+/// Open an output for writing, `-` is stdout
+///
+/// Missing parent directories are created.
+pub fn create_output(path: &Path) -> Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,6 +273,32 @@ mod tests {
         ];
         assert_eq!(tokenize("ADD R0,R2,#14 ;COMMENT"), Ok(Some(check2)));
         assert_eq!(tokenize(";COMMENT2"), Ok(None));
+        assert_eq!(
+            tokenize(".STRINGZ \"Hi; there\" ; greeting"),
+            Ok(Some(vec![
+                Token::Dir(Directive::Stringz),
+                Token::Str(String::from("Hi; there"))
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_decode_lines() {
+        assert_eq!(
+            decode_lines(b"ADD R0,R0,#1\r\n\nHALT\n", "a.asm"),
+            Ok(vec![
+                String::from("ADD R0,R0,#1"),
+                String::new(),
+                String::from("HALT")
+            ])
+        );
+
+        let err = decode_lines(b".ORIG x3000\nHALT \xff\n", "a.asm").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::EncodingError);
+        assert_eq!(
+            err.to_string(),
+            "[EncodingError] invalid UTF-8 in a.asm on line 2 (byte 6)"
+        );
     }
 
     #[test]
@@ -135,4 +320,56 @@ mod tests {
             Err(ErrorKind::ValueError)
         );
     }
+
+    #[test]
+    fn test_output_paths() {
+        let paths = OutputPaths::from_input(Path::new("asm/test.ggnm"));
+        assert_eq!(paths.resolve("obj"), Some(PathBuf::from("test.obj")));
+
+        let paths = OutputPaths {
+            out_dir: Some(PathBuf::from("build")),
+            sym: Some(PathBuf::from("symbols/os.sym")),
+            ..OutputPaths::new("os.v2")
+        };
+        assert_eq!(paths.resolve("obj"), Some(PathBuf::from("build/os.v2.obj")));
+        assert_eq!(paths.resolve("sym"), Some(PathBuf::from("symbols/os.sym")));
+
+        let paths = OutputPaths::from_input(Path::new("-"));
+        assert_eq!(paths.resolve("obj"), Some(PathBuf::from("out.obj")));
+
+        let paths = OutputPaths::new("-");
+        assert_eq!(paths.resolve("obj"), Some(PathBuf::from("-")));
+        assert_eq!(paths.resolve("sym"), None);
+        assert_eq!(paths.resolve_image("hex", true), Some(PathBuf::from("-")));
+        assert_eq!(paths.resolve_image("obj", false), None);
+        assert_eq!(paths.check_written(&["hex"], &[]), Ok(()));
+        assert_eq!(
+            paths
+                .check_written(&["hex", "srec"], &["map", "lst"])
+                .unwrap_err()
+                .to_string(),
+            "[ValueError] only one output can go to stdout, .srec, .map, .lst would not be written"
+        );
+
+        let paths = OutputPaths {
+            obj: Some(PathBuf::from("out.obj")),
+            ..OutputPaths::new("-")
+        };
+        assert_eq!(paths.check_written(&["hex", "obj"], &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_pc_offset() {
+        assert_eq!(verify_pc_offset(0x00ff, 9), Ok(0x00ff));
+        assert_eq!(verify_pc_offset(0xff00, 9), Ok(0x0100));
+        assert_eq!(verify_pc_offset(0x03ff, 11), Ok(0x03ff));
+        assert_eq!(
+            verify_pc_offset(0x0100, 9).map_err(|e| e.kind),
+            Err(ErrorKind::ValueError)
+        );
+        assert_eq!(
+            verify_pc_offset(0xfbff, 11).map_err(|e| e.kind),
+            Err(ErrorKind::ValueError)
+        );
+    }
 }